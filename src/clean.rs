@@ -0,0 +1,158 @@
+//! `--clean`：删除更新失败或中断后残留的临时文件和备份，不会触及 data/ 和配置文件
+
+use crate::{fsutil, updater};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 程序目录下的临时文件，包括旧版本使用过的名称
+const TOP_LEVEL_ARTIFACTS: &[&str] = &[
+    "_cli_update_temp",
+    "_cli_update_temp.download",
+    "_cli_update_temp.staging",
+    "_cli_update_temp.old",
+    "_temp_extract",
+    "_temp_download.tgz",
+];
+
+/// bin/ 下每个组件目录旁边的临时目录后缀，见 `updater::install_atomically`
+const COMPONENT_SUFFIXES: &[&str] = &["download", "staging", "old"];
+
+/// 清理后返回退出码；有相关进程在运行时拒绝清理
+pub fn run(exe_dir: &Path) -> i32 {
+    let running: Vec<(String, u32)> = updater::check_running_processes()
+        .into_iter()
+        .filter(|(name, _)| !name.trim_end_matches(".exe").eq_ignore_ascii_case("qq"))
+        .collect();
+    if !running.is_empty() {
+        eprintln!("错误: 以下进程正在运行，请先停止后再清理:");
+        for (name, pid) in &running {
+            eprintln!("  - {} (PID: {})", name, pid);
+        }
+        return 1;
+    }
+
+    let mut freed = 0;
+    let mut removed = 0;
+    let mut failed = 0;
+    for path in artifacts(exe_dir) {
+        let size = fsutil::disk_usage(&path);
+        let result = if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        match result {
+            Ok(()) => {
+                println!("已删除 {} ({})", path.display(), format_size(size));
+                freed += size;
+                removed += 1;
+            }
+            Err(e) => {
+                eprintln!("删除 {} 失败: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    if removed == 0 && failed == 0 {
+        println!("没有需要清理的文件");
+    } else {
+        println!("共删除 {} 项，释放 {}", removed, format_size(freed));
+    }
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// 收集存在的临时文件；备份文件只有在对应的程序仍存在时才会列入
+fn artifacts(exe_dir: &Path) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = TOP_LEVEL_ARTIFACTS
+        .iter()
+        .map(|name| exe_dir.join(name))
+        .filter(|p| p.exists())
+        .collect();
+
+    let bin_dir = exe_dir.join("bin");
+    for component in ["pmhq", "llbot"] {
+        let live = bin_dir.join(component);
+        for suffix in COMPONENT_SUFFIXES {
+            let path = bin_dir.join(format!("{}.{}", component, suffix));
+            if !path.exists() {
+                continue;
+            }
+            // 替换中断时组件目录可能只剩 .old，下次更新会用它恢复
+            if *suffix == "old" && !live.is_dir() {
+                warning!("{} 不存在，保留备份 {}", live.display(), path.display());
+                continue;
+            }
+            found.push(path);
+        }
+    }
+
+    // 下载中断留下的压缩包和 .part 文件，只匹配更新时使用的文件名，不删除用户放置的压缩包
+    let pending_dir = exe_dir.join(updater::PENDING_DIR);
+    for dir in [exe_dir, bin_dir.as_path(), pending_dir.as_path()] {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let archive = name.strip_suffix(".part").unwrap_or(&name);
+            if updater::is_download_archive(archive) {
+                let path = entry.path();
+                if !found.contains(&path) {
+                    found.push(path);
+                }
+            }
+        }
+    }
+
+    // 自更新留下的 <exe>.bak 和回滚中断留下的 <exe>.rollback，只匹配启动器本身
+    let exe_name = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_else(|| crate::get_exe_name("llbot"));
+    let rollback_name = Path::new(&exe_name)
+        .with_extension(if cfg!(target_os = "windows") { "exe.rollback" } else { "rollback" });
+    for backup in [exe_dir.join(format!("{}.bak", exe_name)), exe_dir.join(rollback_name)] {
+        push_backup(&mut found, backup, &exe_dir.join(&exe_name));
+    }
+
+    // 组件目录中旧版本更新留下的 <文件>.bak
+    for component in ["pmhq", "llbot"] {
+        let dir = bin_dir.join(component);
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(live_name) = name.strip_suffix(".bak") {
+                push_backup(&mut found, entry.path(), &dir.join(live_name));
+            }
+        }
+    }
+    found
+}
+
+/// 备份文件存在且对应的文件仍在时列入，否则保留备份以便恢复
+fn push_backup(found: &mut Vec<PathBuf>, backup: PathBuf, live: &Path) {
+    if !backup.is_file() {
+        return;
+    }
+    if live.exists() {
+        found.push(backup);
+    } else {
+        warning!("{} 不存在，保留备份 {}", live.display(), backup.display());
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    let mb = bytes as f64 / 1024.0 / 1024.0;
+    if mb >= 1.0 {
+        format!("{:.1} MB", mb)
+    } else {
+        format!("{} KB", bytes.div_ceil(1024))
+    }
+}
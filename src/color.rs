@@ -0,0 +1,106 @@
+//! 终端颜色输出控制
+
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+const AUTO: u8 = 0;
+const ALWAYS: u8 = 1;
+const NEVER: u8 = 2;
+
+static MODE: AtomicU8 = AtomicU8::new(AUTO);
+
+/// 设置 `--color=` 的取值，未知取值返回 false
+pub fn set_mode(value: &str) -> bool {
+    let mode = match value {
+        "auto" => AUTO,
+        "always" => ALWAYS,
+        "never" => NEVER,
+        _ => return false,
+    };
+    MODE.store(mode, Ordering::Relaxed);
+    true
+}
+
+pub fn force(enabled: bool) {
+    MODE.store(if enabled { ALWAYS } else { NEVER }, Ordering::Relaxed);
+}
+
+/// 是否输出颜色：命令行参数优先，其次 `NO_COLOR` / `CLICOLOR_FORCE` / `CLICOLOR`，
+/// 最后在 auto 模式下仅当 stdout 是终端时启用
+pub fn enabled() -> bool {
+    match MODE.load(Ordering::Relaxed) {
+        ALWAYS => return true,
+        NEVER => return false,
+        _ => {}
+    }
+    if std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        return false;
+    }
+    if std::env::var("CLICOLOR_FORCE").is_ok_and(|v| !v.is_empty() && v != "0") {
+        return true;
+    }
+    if std::env::var("CLICOLOR").is_ok_and(|v| v == "0") {
+        return false;
+    }
+    std::io::stdout().is_terminal()
+}
+
+fn paint(text: &str, code: &str) -> String {
+    if enabled() {
+        format!("\x1B[{}m{}\x1B[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn green(text: &str) -> String {
+    paint(text, "32")
+}
+
+pub fn yellow(text: &str) -> String {
+    paint(text, "33")
+}
+
+pub fn red(text: &str) -> String {
+    paint(text, "31")
+}
+
+/// 文本在终端中占用的列数：中日韩文字和全角符号占两列，控制符不占列
+pub fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1B' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        width += match c as u32 {
+            0x00..=0x1F | 0x7F => 0,
+            0x1100..=0x115F
+            | 0x2E80..=0x303E
+            | 0x3041..=0x33FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F300..=0x1F64F
+            | 0x20000..=0x3FFFD => 2,
+            _ => 1,
+        };
+    }
+    width
+}
+
+/// 在右侧补空格直到占满 `width` 列
+pub fn pad(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(text));
+    format!("{}{}", text, " ".repeat(padding))
+}
@@ -0,0 +1,212 @@
+//! 生成 shell 补全脚本
+
+/// 补全用的参数描述，`values` 为 None 表示开关，空切片表示接受任意取值
+struct Flag {
+    name: &'static str,
+    help: &'static str,
+    values: Option<&'static [&'static str]>,
+}
+
+const fn switch(name: &'static str, help: &'static str) -> Flag {
+    Flag { name, help, values: None }
+}
+
+const fn value(name: &'static str, help: &'static str, values: &'static [&'static str]) -> Flag {
+    Flag { name, help, values: Some(values) }
+}
+
+/// 启动器参数和常用的 PMHQ 透传参数，新增参数时需同步更新
+const FLAGS: &[Flag] = &[
+    value("--qq-path", "QQ 可执行文件路径", &[]),
+    switch("--no-qq-check", "跳过 QQ 路径检查"),
+    value("--qq-url", "自动安装 QQ 时使用的安装包地址", &[]),
+    value("--qq-sha256", "QQ 安装包的 SHA-256", &[]),
+    switch("--qq-no-verify", "不校验 QQ 安装包"),
+    value("--qq", "快速登录 QQ 号", &[]),
+    value("--account", "多账号时指定要使用的 QQ 号", &[]),
+    switch("--headless", "无头模式"),
+    value("--qr-style", "终端二维码绘制方式", &["block", "invert", "ansi"]),
+    switch("--qr-svg", "同时生成 SVG 格式的二维码"),
+    value("--qr-svg-module-size", "SVG 二维码每个模块的边长", &[]),
+    switch("--qr-svg-no-quiet-zone", "SVG 二维码不保留静区"),
+    value("--qrcode-path", "二维码图片的保存位置", &[]),
+    switch("--qr-stdout-only", "只在终端显示二维码，不写入文件"),
+    switch("--log-qr", "同时把二维码写入日志文件"),
+    value("--export-qr-ascii", "同时把二维码以纯 ASCII 写入该文本文件", &[]),
+    value("--on-login-webhook", "登录成功后通知的地址", &[]),
+    switch("--qq-console", "启用 QQ 控制台日志"),
+    switch("--debug", "调试模式"),
+    value("--debug-pb", "显示 send/recv Protobuf 日志", &["true", "false"]),
+    value("--work-dir", "工作目录", &[]),
+    value("--sub-cmd-workdir", "子命令工作目录", &[]),
+    value("--env", "为 PMHQ 和 node 设置环境变量 KEY=VALUE，可重复", &[]),
+    value("--data-dir", "数据目录", &[]),
+    value("--node-path", "使用指定的 node 代替自带的 node", &[]),
+    value("--node-arg", "追加给 node 的参数，可重复", &[]),
+    value("--llbot-arg", "追加给 llbot.js 的参数，可重复", &[]),
+    switch("--update", "检查并执行更新"),
+    value("--update", "只更新指定的组件", &["cli", "pmhq", "llbot"]),
+    value("--update-from", "从本地压缩包更新，格式为 <路径>[:组件]", &[]),
+    value("--install-version", "安装指定版本的组件，格式为 <组件>@<版本>", &[]),
+    switch("--check-update", "只检查更新，有更新时退出码为 10"),
+    switch("--list-versions", "以 JSON 输出各组件的版本"),
+    switch("--background", "与 --update 一起使用，下次启动时再应用更新"),
+    switch("--rollback", "回滚到自更新前的 CLI 版本"),
+    switch("--kill-qq", "启动前结束正在运行的 QQ"),
+    switch("--stop", "结束正在运行的 LLBot 相关进程后退出"),
+    switch("--no-verify", "更新时跳过校验和检查"),
+    switch("--allow-prerelease", "允许更新到预发布版本"),
+    switch("--reinstall", "重新下载 PMHQ 和 LLBot"),
+    value("--login-settle", "登录后等待账号信息就绪的最长秒数", &[]),
+    value("--login-timeout", "整个登录阶段的最长秒数", &[]),
+    switch("--wait-for-login", "登录成功后才继续运行，失败时以非零状态码退出"),
+    value("--request-timeout", "PMHQ 请求的超时秒数", &[]),
+    value("--login-token", "使用保存的会话令牌登录", &[]),
+    value("--ca-cert", "额外信任的 CA 证书（PEM）", &[]),
+    value("--proxy", "HTTP 代理地址", &[]),
+    value("--mirror-timeout", "单个 registry 请求的超时秒数", &[]),
+    value("--registry", "只使用指定的 npm registry", &[]),
+    value("--color", "控制彩色输出", &["auto", "always", "never"]),
+    switch("--force-color", "强制彩色输出"),
+    switch("--no-color", "禁用彩色输出"),
+    value("--port", "固定使用的端口", &[]),
+    value("--bind", "PMHQ 监听的地址", &[]),
+    value("--port-range", "扫描可用端口的范围 START-END", &[]),
+    switch("--ephemeral-port", "由系统分配临时端口"),
+    switch("--restart-on-crash", "PMHQ 异常退出时自动重启"),
+    value("--restart-max", "10 分钟内最多自动重启的次数", &[]),
+    value("--watchdog-interval", "定期探测 PMHQ 是否卡死的间隔秒数", &[]),
+    value("--watchdog-failures", "连续探测失败多少次后结束卡死的 PMHQ", &[]),
+    switch("--json-logs", "以 NDJSON 格式转发 PMHQ 输出"),
+    switch("--json", "以 NDJSON 事件输出启动状态"),
+    switch("--list-accounts", "列出本机登录过的账号后退出"),
+    switch("--print-paths", "列出启动器查找的路径后退出"),
+    value("--profile", "使用 llbot.toml 中定义的启动配置", &[]),
+    switch("--print-config", "输出解析后的路径和设置后退出"),
+    switch("--clean", "删除更新残留的临时文件和备份后退出"),
+    switch("--doctor", "检查安装是否完整后退出"),
+    switch("--migrate-dry-run", "列出将要迁移的旧版文件后退出"),
+    switch("--no-migrate", "不迁移旧版文件"),
+    switch("--foreground", "在当前终端中运行，出错时不等待按键"),
+    switch("--daemon", "后台运行，输出只写入日志文件"),
+    value("--log-level", "写入日志文件的级别", &["off", "error", "warn", "info", "debug"]),
+    switch("--strict", "将警告视为错误"),
+    switch("--quiet", "不输出启动器自身的提示信息"),
+    value("--status-port", "在本机该端口提供 /status 状态接口", &[]),
+    switch("--dump-sse", "原样输出 PMHQ 事件流，用于调试"),
+    switch("--verbose", "输出启动器的详细诊断信息"),
+    value("--completions", "输出 shell 补全脚本", &["bash", "zsh", "fish", "powershell"]),
+    switch("--help", "显示帮助信息"),
+    switch("--version", "显示版本信息"),
+];
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+pub fn generate(shell: &str, bin: &str) -> Option<String> {
+    match shell {
+        "bash" => Some(bash(bin)),
+        "zsh" => Some(zsh(bin)),
+        "fish" => Some(fish(bin)),
+        "powershell" => Some(powershell(bin)),
+        _ => None,
+    }
+}
+
+fn bash(bin: &str) -> String {
+    let func = format!("_{}", bin.replace('-', "_"));
+    let words: Vec<String> = FLAGS
+        .iter()
+        .map(|f| match f.values {
+            Some(_) => format!("{}=", f.name),
+            None => f.name.to_string(),
+        })
+        .collect();
+
+    let mut cases = String::new();
+    for f in FLAGS {
+        if let Some(values) = f.values.filter(|v| !v.is_empty()) {
+            cases.push_str(&format!(
+                "        {}=*)\n            COMPREPLY=($(compgen -W \"{}\" -- \"${{cur#*=}}\"))\n            return ;;\n",
+                f.name,
+                values.join(" ")
+            ));
+        }
+    }
+
+    format!(
+        r#"{func}() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    case "$cur" in
+{cases}        --*=*)
+            return ;;
+    esac
+    COMPREPLY=($(compgen -W "{words}" -- "$cur"))
+    [[ ${{COMPREPLY[0]}} == *= ]] && compopt -o nospace
+}}
+complete -F {func} {bin}
+"#,
+        words = words.join(" "),
+    )
+}
+
+fn zsh(bin: &str) -> String {
+    let mut specs = String::new();
+    for f in FLAGS {
+        let spec = match f.values {
+            None => format!("'{}[{}]'", f.name, f.help),
+            Some([]) => format!("'{}=[{}]:value:'", f.name, f.help),
+            Some(values) => format!("'{}=[{}]:value:({})'", f.name, f.help, values.join(" ")),
+        };
+        specs.push_str(&format!("    {} \\\n", spec));
+    }
+    format!(
+        "#compdef {bin}\n\n_arguments -s \\\n{specs}    '*::arg:_files'\n",
+        bin = bin,
+        specs = specs
+    )
+}
+
+fn fish(bin: &str) -> String {
+    let mut out = String::new();
+    for f in FLAGS {
+        let long = f.name.trim_start_matches("--");
+        let line = match f.values {
+            None => format!("complete -c {} -l {} -d '{}'\n", bin, long, f.help),
+            Some([]) => format!("complete -c {} -l {} -r -d '{}'\n", bin, long, f.help),
+            Some(values) => format!(
+                "complete -c {} -l {} -x -a '{}' -d '{}'\n",
+                bin,
+                long,
+                values.join(" "),
+                f.help
+            ),
+        };
+        out.push_str(&line);
+    }
+    out
+}
+
+fn powershell(bin: &str) -> String {
+    let mut entries = String::new();
+    for f in FLAGS {
+        match f.values {
+            Some(values) if !values.is_empty() => {
+                for v in values {
+                    entries.push_str(&format!("        '{}={}'\n", f.name, v));
+                }
+            }
+            Some(_) => entries.push_str(&format!("        '{}='\n", f.name)),
+            None => entries.push_str(&format!("        '{}'\n", f.name)),
+        }
+    }
+    format!(
+        r#"Register-ArgumentCompleter -Native -CommandName '{bin}', '{bin}.exe' -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    @(
+{entries}    ) | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }}
+}}
+"#
+    )
+}
@@ -0,0 +1,148 @@
+//! 启动器配置文件 llbot.toml
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const CONFIG_FILE: &str = "llbot.toml";
+
+/// llbot.toml 中可设置的默认值，未知字段会被忽略
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LauncherConfig {
+    pub port: Option<u16>,
+    /// 形如 "13000-14000"
+    pub port_range: Option<String>,
+    pub qq_path: Option<String>,
+    pub headless: Option<bool>,
+    pub registry_mirror: Option<String>,
+    /// 只使用该 registry 检查和下载更新
+    pub registry: Option<String>,
+    /// 自动安装 QQ 时使用的安装包地址及其 SHA-256
+    pub qq_url: Option<String>,
+    pub qq_sha256: Option<String>,
+    /// 额外信任的 CA 证书（PEM），等同于 `--ca-cert`
+    pub ca_cert: Option<String>,
+    /// 为 false 时不迁移旧版文件，等同于 `--no-migrate`
+    pub migrate: Option<bool>,
+    /// `[profiles.<name>]`，用 `--profile=<name>` 选择
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// 启动配置，优先级介于命令行参数和 llbot.toml 顶层设置之间
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub port: Option<u16>,
+    pub data_dir: Option<String>,
+    pub headless: Option<bool>,
+    pub registry: Option<String>,
+    /// 等同于 `--llbot-arg` / `--node-arg`，命令行中给出时整体被替换
+    pub llbot_args: Vec<String>,
+    pub node_args: Vec<String>,
+}
+
+impl Profile {
+    fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(port) = self.port {
+            args.push(format!("--port={}", port));
+        }
+        if let Some(ref dir) = self.data_dir {
+            args.push(format!("--data-dir={}", dir));
+        }
+        if let Some(ref registry) = self.registry {
+            args.push(format!("--registry={}", registry));
+        }
+        if self.headless == Some(true) {
+            args.push("--headless".to_string());
+        }
+        args.extend(self.llbot_args.iter().map(|a| format!("--llbot-arg={}", a)));
+        args.extend(self.node_args.iter().map(|a| format!("--node-arg={}", a)));
+        args
+    }
+}
+
+impl LauncherConfig {
+    /// 读取程序目录下的 llbot.toml，文件不存在时返回默认配置
+    pub fn load(exe_dir: &Path) -> Result<Self, String> {
+        let path = exe_dir.join(CONFIG_FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("读取 {} 失败: {}", path.display(), e)),
+        };
+        toml::from_str(&content).map_err(|e| format!("解析 {} 失败: {}", path.display(), e))
+    }
+
+    /// 把配置转换为命令行参数插入到最前面；命令行中已出现的参数不会被覆盖，
+    /// 选择了 `profile` 时其设置优先于顶层设置
+    pub fn apply_to_args(&self, args: &mut Vec<String>, profile: Option<&str>) -> Result<(), String> {
+        let profile = match profile {
+            Some(name) => Some(self.profiles.get(name).ok_or_else(|| {
+                let names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                if names.is_empty() {
+                    format!("{} 中没有定义任何配置，找不到 \"{}\"", CONFIG_FILE, name)
+                } else {
+                    format!("找不到配置 \"{}\"，可用的配置: {}", name, names.join(", "))
+                }
+            })?),
+            None => None,
+        };
+        if let Some(profile) = profile {
+            prepend_defaults(args, profile.to_args());
+        }
+
+        let mut defaults = Vec::new();
+        if let Some(port) = self.port {
+            defaults.push(format!("--port={}", port));
+        }
+        if let Some(ref range) = self.port_range {
+            defaults.push(format!("--port-range={}", range));
+        }
+        if let Some(ref qq_path) = self.qq_path {
+            defaults.push(format!("--qq-path={}", qq_path));
+        }
+        if let Some(ref registry) = self.registry {
+            defaults.push(format!("--registry={}", registry));
+        }
+        if let Some(ref url) = self.qq_url {
+            defaults.push(format!("--qq-url={}", url));
+        }
+        if let Some(ref sha256) = self.qq_sha256 {
+            defaults.push(format!("--qq-sha256={}", sha256));
+        }
+        if let Some(ref path) = self.ca_cert {
+            defaults.push(format!("--ca-cert={}", path));
+        }
+        // 配置中明确设置了 headless = false 时不使用顶层的 headless
+        if self.headless == Some(true) && profile.is_none_or(|p| p.headless.is_none()) {
+            defaults.push("--headless".to_string());
+        }
+        if self.migrate == Some(false) {
+            defaults.push("--no-migrate".to_string());
+        }
+
+        prepend_defaults(args, defaults);
+        Ok(())
+    }
+}
+
+/// 互斥的端口参数：命令行给出其中任意一个时，配置中的其余几个也不再生效
+const PORT_FLAGS: &[&str] = &["--port", "--port-range", "--ephemeral-port"];
+
+/// 跳过 `args` 中已出现的参数（端口参数按 [`PORT_FLAGS`] 整组判断），其余插入到最前面
+fn prepend_defaults(args: &mut Vec<String>, mut defaults: Vec<String>) {
+    let present = |key: &str| args.iter().any(|a| a.split('=').next() == Some(key));
+    let port_given = PORT_FLAGS.iter().any(|key| present(key));
+    defaults.retain(|arg| {
+        let key = arg.split('=').next().unwrap_or(arg);
+        if PORT_FLAGS.contains(&key) {
+            !port_given
+        } else {
+            !present(key)
+        }
+    });
+    args.splice(0..0, defaults);
+}
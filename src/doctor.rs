@@ -0,0 +1,197 @@
+//! `--doctor`：检查安装是否完整，逐项输出结果
+
+use crate::{color, find_available_port, find_pmhq_exe, get_exe_name, updater};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::net::IpAddr;
+use std::path::Path;
+
+enum Outcome {
+    Pass,
+    /// 不影响启动，但可能导致部分功能不可用
+    Warn,
+    Fail,
+}
+
+struct Report {
+    passed: usize,
+    warned: usize,
+    failed: usize,
+}
+
+impl Report {
+    fn record(&mut self, outcome: Outcome, message: &str) {
+        let tag = match outcome {
+            Outcome::Pass => {
+                self.passed += 1;
+                color::green("[通过]")
+            }
+            Outcome::Warn => {
+                self.warned += 1;
+                color::yellow("[警告]")
+            }
+            Outcome::Fail => {
+                self.failed += 1;
+                color::red("[失败]")
+            }
+        };
+        println!("{} {}", tag, message);
+    }
+}
+
+/// 逐项检查后返回退出码：有关键项失败时为 1
+pub fn run(exe_dir: &Path, node: &Path, bind: IpAddr, port_range: (u16, u16)) -> i32 {
+    let mut report = Report { passed: 0, warned: 0, failed: 0 };
+    check_pmhq(exe_dir, &mut report);
+    check_node(exe_dir, node, &mut report);
+
+    let llbot_js = exe_dir.join("bin/llbot/llbot.js");
+    if llbot_js.is_file() {
+        report.record(Outcome::Pass, &format!("llbot.js: {}", llbot_js.display()));
+    } else {
+        report.record(Outcome::Fail, &format!("未找到 llbot.js: {}", llbot_js.display()));
+    }
+
+    match check_writable(exe_dir) {
+        Ok(()) => report.record(Outcome::Pass, &format!("程序目录可写: {}", exe_dir.display())),
+        Err(e) => report.record(
+            Outcome::Fail,
+            &format!("程序目录不可写: {}: {}", exe_dir.display(), e),
+        ),
+    }
+
+    let (start, end) = port_range;
+    match find_available_port(bind, start, end) {
+        Some(port) => report.record(Outcome::Pass, &format!("可用端口: {} ({})", port, bind)),
+        None => report.record(
+            Outcome::Fail,
+            &format!("{} 上 {}-{} 范围内没有可用端口", bind, start, end),
+        ),
+    }
+
+    // 无法访问 registry 只影响更新
+    match updater::check_registry(exe_dir) {
+        Ok(version) => report.record(
+            Outcome::Pass,
+            &format!("npm registry 可访问，LLBot 最新版本 {}", version),
+        ),
+        Err(e) => report.record(Outcome::Warn, &format!("npm registry 不可访问，无法更新: {}", e)),
+    }
+
+    println!();
+    println!(
+        "共检查 {} 项: {} 项通过，{} 项警告，{} 项失败",
+        report.passed + report.warned + report.failed,
+        report.passed,
+        report.warned,
+        report.failed
+    );
+    if report.failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+fn check_pmhq(exe_dir: &Path, report: &mut Report) {
+    let Some(pmhq) = find_pmhq_exe(exe_dir) else {
+        report.record(
+            Outcome::Fail,
+            &format!("未找到 pmhq: {}", exe_dir.join("bin/pmhq").display()),
+        );
+        return;
+    };
+    match binary_arch(&pmhq) {
+        Ok(Some(arch)) if arch == std::env::consts::ARCH => {
+            report.record(Outcome::Pass, &format!("pmhq ({}): {}", arch, pmhq.display()))
+        }
+        Ok(Some(arch)) => report.record(
+            Outcome::Fail,
+            &format!(
+                "pmhq 的架构为 {}，与当前系统 {} 不符: {}",
+                arch,
+                std::env::consts::ARCH,
+                pmhq.display()
+            ),
+        ),
+        Ok(None) => report.record(
+            Outcome::Warn,
+            &format!("无法识别 pmhq 的文件格式: {}", pmhq.display()),
+        ),
+        Err(e) => report.record(Outcome::Fail, &format!("读取 {} 失败: {}", pmhq.display(), e)),
+    }
+}
+
+/// `node` 为 `--node-path` 指定的外部 node 时，缺少执行权限不会被自动修复，视为失败
+fn check_node(exe_dir: &Path, node: &Path, report: &mut Report) {
+    let bundled = node == exe_dir.join("bin/llbot").join(get_exe_name("node"));
+    if !node.is_file() {
+        report.record(Outcome::Fail, &format!("未找到 node: {}", node.display()));
+        return;
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let executable = fs::metadata(node).is_ok_and(|m| m.permissions().mode() & 0o111 != 0);
+        if !executable {
+            // 自带的 node 在正常启动时会自动添加执行权限
+            let outcome = if bundled { Outcome::Warn } else { Outcome::Fail };
+            report.record(outcome, &format!("node 缺少执行权限: {}", node.display()));
+            return;
+        }
+    }
+    let source = if bundled { "" } else { " (--node-path)" };
+    report.record(Outcome::Pass, &format!("node: {}{}", node.display(), source));
+}
+
+/// 在 `dir` 中创建并删除一个探测文件
+pub fn check_writable(dir: &Path) -> io::Result<()> {
+    let probe = dir.join(".llbot-doctor");
+    fs::write(&probe, b"")?;
+    fs::remove_file(&probe)
+}
+
+/// 根据 ELF / PE / Mach-O 文件头判断可执行文件的架构，返回与 `std::env::consts::ARCH`
+/// 相同的名称；无法识别的格式（例如脚本）返回 None
+pub fn binary_arch(path: &Path) -> io::Result<Option<&'static str>> {
+    let mut header = Vec::new();
+    File::open(path)?.take(4096).read_to_end(&mut header)?;
+    let u16_le = |at: usize| header.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_le = |at: usize| {
+        header
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    let arch = if header.starts_with(b"\x7fELF") {
+        match u16_le(18) {
+            Some(0x3E) => Some("x86_64"),
+            Some(0xB7) => Some("aarch64"),
+            Some(0x03) => Some("x86"),
+            _ => None,
+        }
+    } else if header.starts_with(b"MZ") {
+        let pe = u32_le(0x3C).map(|offset| offset as usize);
+        match pe.filter(|&at| header.get(at..at + 4) == Some(b"PE\0\0")) {
+            Some(at) => match u16_le(at + 4) {
+                Some(0x8664) => Some("x86_64"),
+                Some(0xAA64) => Some("aarch64"),
+                Some(0x014C) => Some("x86"),
+                _ => None,
+            },
+            None => None,
+        }
+    } else if header.starts_with(&[0xCF, 0xFA, 0xED, 0xFE]) {
+        match u32_le(4) {
+            Some(0x0100_0007) => Some("x86_64"),
+            Some(0x0100_000C) => Some("aarch64"),
+            _ => None,
+        }
+    } else if header.starts_with(&[0xCA, 0xFE, 0xBA, 0xBE]) {
+        // 通用二进制同时包含多个架构
+        Some(std::env::consts::ARCH)
+    } else {
+        None
+    };
+    Ok(arch)
+}
@@ -0,0 +1,40 @@
+//! `--json` 模式下输出到 stdout 的 NDJSON 状态事件
+//!
+//! 启用后 stdout 只输出事件和 PMHQ 的 NDJSON 日志，启动器的提示信息改为输出到 stderr。
+
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 输出一行 `{"event": ..., "ts": ..., ...fields}`，未启用 `--json` 时不做任何事
+pub fn emit(event: &str, fields: Value) {
+    if !enabled() {
+        return;
+    }
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    let mut record = Map::new();
+    record.insert("event".to_string(), Value::from(event));
+    record.insert("ts".to_string(), Value::from(ts));
+    if let Value::Object(fields) = fields {
+        record.extend(fields);
+    }
+
+    let mut out = std::io::stdout().lock();
+    let _ = writeln!(out, "{}", Value::Object(record));
+    let _ = out.flush();
+}
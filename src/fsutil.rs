@@ -0,0 +1,113 @@
+//! 迁移旧版文件、安装更新和清理时共用的文件操作
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// 递归复制 `src` 到 `dst`：符号链接按原样重建而不复制其指向的内容，Unix 下保留权限位；
+/// 复制过程中被删除的条目会被跳过，不影响其余文件
+pub fn copy_recursive(src: &Path, dst: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(src) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            verbose!("复制时 {} 已不存在，跳过", src.display());
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    if metadata.file_type().is_symlink() {
+        return copy_symlink(src, dst);
+    }
+    if metadata.is_dir() {
+        fs::create_dir_all(dst)?;
+        for entry in fs::read_dir(src)? {
+            match entry {
+                Ok(entry) => copy_recursive(&entry.path(), &dst.join(entry.file_name()))?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        // 目录内容复制完成后再设置权限，避免只读目录无法写入
+        return fs::set_permissions(dst, metadata.permissions());
+    }
+
+    match fs::copy(src, dst) {
+        Ok(_) => fs::set_permissions(dst, metadata.permissions()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !src.exists() => {
+            verbose!("复制时 {} 已不存在，跳过", src.display());
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// `path` 占用的字节数，目录时递归累加，不跟随符号链接；无法读取的条目按 0 计算
+pub fn disk_usage(path: &Path) -> u64 {
+    fn walk(path: &Path) -> io::Result<u64> {
+        let metadata = fs::symlink_metadata(path)?;
+        if !metadata.is_dir() {
+            return Ok(metadata.len());
+        }
+        let mut total = 0;
+        for entry in fs::read_dir(path)? {
+            total += walk(&entry?.path()).unwrap_or(0);
+        }
+        Ok(total)
+    }
+    walk(path).unwrap_or(0)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn copy_symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    let target = fs::read_link(src)?;
+    remove_existing(dst)?;
+    std::os::unix::fs::symlink(target, dst)
+}
+
+/// Windows 下创建符号链接需要管理员权限或开发者模式，失败时改为复制链接指向的内容
+#[cfg(target_os = "windows")]
+fn copy_symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+
+    let target = fs::read_link(src)?;
+    remove_existing(dst)?;
+    let is_dir = fs::metadata(src).is_ok_and(|m| m.is_dir());
+    let created = if is_dir {
+        symlink_dir(&target, dst)
+    } else {
+        symlink_file(&target, dst)
+    };
+    match created {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            verbose!("无法创建符号链接 {}: {}，改为复制内容", dst.display(), e);
+            if is_dir {
+                copy_recursive_following(src, dst)
+            } else {
+                fs::copy(src, dst).map(|_| ())
+            }
+        }
+    }
+}
+
+/// 跟随符号链接复制目录，仅用于 Windows 下无法创建链接的情况
+#[cfg(target_os = "windows")]
+fn copy_recursive_following(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        copy_recursive(&entry.path(), &dst.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+/// 删除已存在的文件或链接，以便在原位置创建新的符号链接
+fn remove_existing(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
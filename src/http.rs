@@ -0,0 +1,304 @@
+//! HTTP 请求的公共封装
+
+// 原样透传 ureq 的错误类型，调用方无需关心封装
+#![allow(clippy::result_large_err)]
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// 断点续传下载的最多尝试次数
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+
+static PROXY_ARG: OnceLock<String> = OnceLock::new();
+static TLS_CONFIG: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+static DIRECT_AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+
+/// 设置 `--proxy` 指定的代理，优先于环境变量；需在发出第一个请求前调用
+pub fn set_proxy(url: &str) -> Result<(), String> {
+    ureq::Proxy::new(url).map_err(|e| format!("代理地址无效: {}", e))?;
+    let _ = PROXY_ARG.set(url.to_string());
+    Ok(())
+}
+
+/// 在内置根证书之外信任 `path` 中的 PEM 证书（`--ca-cert`）；需在发出第一个请求前调用
+pub fn set_ca_cert(path: &Path) -> Result<(), String> {
+    use rustls_pki_types::pem::PemObject;
+    use rustls_pki_types::CertificateDer;
+
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("读取 CA 证书 {} 失败: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("{} 中没有 PEM 格式的证书", path.display()));
+    }
+
+    let mut roots = rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let count = certs.len();
+    for cert in certs {
+        roots
+            .add(cert)
+            .map_err(|e| format!("CA 证书 {} 无效: {}", path.display(), e))?;
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS12, &rustls::version::TLS13])
+        .map_err(|e| e.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let _ = TLS_CONFIG.set(Arc::new(config));
+    verbose!("已加载 {} 个 CA 证书: {}", count, path.display());
+    Ok(())
+}
+
+/// `--proxy` 指定的代理地址
+pub fn proxy_arg() -> Option<&'static str> {
+    PROXY_ARG.get().map(String::as_str)
+}
+
+/// `--proxy` > `HTTPS_PROXY` > `HTTP_PROXY` > `ALL_PROXY`（大小写均可）
+fn proxy_url() -> Option<String> {
+    if let Some(url) = PROXY_ARG.get() {
+        return Some(url.clone());
+    }
+    ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]
+        .iter()
+        .filter_map(|key| std::env::var(key).ok())
+        .find(|v| !v.trim().is_empty())
+}
+
+/// 所有出站请求共用的 agent，配置了代理时经由代理发出
+fn agent() -> &'static ureq::Agent {
+    AGENT.get_or_init(|| {
+        let mut builder = ureq::AgentBuilder::new();
+        if let Some(url) = proxy_url() {
+            match ureq::Proxy::new(&url) {
+                Ok(proxy) => {
+                    verbose!("使用代理: {}", redact_url(&url));
+                    builder = builder.proxy(proxy);
+                }
+                Err(e) => warning!("忽略无效的代理地址 {}: {}", redact_url(&url), e),
+            }
+        }
+        with_tls_config(builder).build()
+    })
+}
+
+/// 指定了 `--ca-cert` 时使用包含该证书的 TLS 配置
+fn with_tls_config(builder: ureq::AgentBuilder) -> ureq::AgentBuilder {
+    match TLS_CONFIG.get() {
+        Some(config) => builder.tls_config(config.clone()),
+        None => builder,
+    }
+}
+
+/// 本机的 PMHQ 接口不经过代理，与常见的 NO_PROXY 默认行为一致
+fn agent_for(url: &str) -> &'static ureq::Agent {
+    let host = url
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(url)
+        .split(['/', '?'])
+        .next()
+        .unwrap_or("");
+    let host = host.rsplit_once(':').map(|(h, _)| h).unwrap_or(host);
+    if matches!(host, "127.0.0.1" | "localhost" | "[::1]") {
+        DIRECT_AGENT.get_or_init(|| with_tls_config(ureq::AgentBuilder::new()).build())
+    } else {
+        agent()
+    }
+}
+
+pub fn get(url: &str) -> ureq::Request {
+    agent_for(url).get(url)
+}
+
+pub fn post(url: &str) -> ureq::Request {
+    agent_for(url).post(url)
+}
+
+/// 发送无请求体的请求，并在 `--verbose` 下记录状态和耗时
+pub fn call(req: ureq::Request) -> Result<ureq::Response, ureq::Error> {
+    let (method, url) = (req.method().to_string(), redact_url(req.url()));
+    let start = Instant::now();
+    let result = req.call();
+    log_result(&method, &url, start, &result);
+    result
+}
+
+/// 发送字符串请求体，并在 `--verbose` 下记录状态和耗时
+pub fn send_string(req: ureq::Request, body: &str) -> Result<ureq::Response, ureq::Error> {
+    let (method, url) = (req.method().to_string(), redact_url(req.url()));
+    let start = Instant::now();
+    let result = req.send_string(body);
+    log_result(&method, &url, start, &result);
+    result
+}
+
+/// 下载到 `dest`：内容先写入 `<dest>.part`，中断后用 `Range` 请求从已下载的位置继续，
+/// 服务器不支持续传（未返回 206）时从头下载；大小与服务器给出的一致后才改名为 `dest`。
+/// `progress` 收到已下载和总字节数，总大小未知时为 0
+pub fn download_resumable(
+    url: &str,
+    dest: &Path,
+    timeout: Duration,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<(), String> {
+    let part = part_path(dest);
+    let mut last_error = String::new();
+    for attempt in 1..=DOWNLOAD_ATTEMPTS {
+        if attempt > 1 {
+            println!();
+            println!("{}，正在重试 ({}/{})...", last_error, attempt, DOWNLOAD_ATTEMPTS);
+        }
+        match download_part(url, &part, timeout, &mut progress) {
+            Ok(()) => {
+                return fs::rename(&part, dest)
+                    .map_err(|e| format!("保存 {} 失败: {}", dest.display(), e));
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+fn part_path(dest: &Path) -> PathBuf {
+    let mut name = dest.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// 下载一次，成功时 `part` 中是完整内容；失败时保留已下载的部分供下次续传
+fn download_part(
+    url: &str,
+    part: &Path,
+    timeout: Duration,
+    progress: &mut impl FnMut(u64, u64),
+) -> Result<(), String> {
+    let offset = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+    // 压缩包按原始字节保存和续传，不让服务器再做一层 gzip 编码
+    let mut req = get(url).timeout(timeout).set("Accept-Encoding", "identity");
+    if offset > 0 {
+        req = req.set("Range", &format!("bytes={}-", offset));
+    }
+    let resp = match call(req) {
+        Ok(resp) => resp,
+        // 已下载的部分比服务器上的文件还大，只能重新下载
+        Err(ureq::Error::Status(416, _)) if offset > 0 => {
+            let _ = fs::remove_file(part);
+            return Err("服务器拒绝续传请求".to_string());
+        }
+        Err(e) => return Err(format!("下载失败: {}", e)),
+    };
+
+    // Content-Range: bytes <start>-<end>/<total>
+    let range = resp
+        .header("Content-Range")
+        .and_then(|r| r.strip_prefix("bytes "))
+        .and_then(|r| r.split_once('/'))
+        .map(|(span, total)| {
+            let start = span.split_once('-').and_then(|(s, _)| s.parse::<u64>().ok());
+            (start, total.parse::<u64>().ok())
+        });
+    let (file, mut downloaded, total) = match (resp.status(), range) {
+        (206, Some((Some(start), total))) if offset > 0 && start == offset => {
+            verbose!("从 {} 字节处继续下载", offset);
+            (OpenOptions::new().append(true).open(part), offset, total)
+        }
+        (200, _) => {
+            if offset > 0 {
+                verbose!("服务器不支持断点续传，从头下载");
+            }
+            let total = resp.header("Content-Length").and_then(|s| s.parse::<u64>().ok());
+            (File::create(part), 0, total)
+        }
+        (status, _) => {
+            let _ = fs::remove_file(part);
+            return Err(format!("HTTP 错误: {}", status));
+        }
+    };
+    let mut file = file.map_err(|e| format!("写入 {} 失败: {}", part.display(), e))?;
+
+    let mut reader = resp.into_reader();
+    let mut buffer = [0u8; 65536];
+    loop {
+        let n = reader
+            .read(&mut buffer)
+            .map_err(|e| format!("读取数据失败: {}", e))?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buffer[..n])
+            .map_err(|e| format!("写入 {} 失败: {}", part.display(), e))?;
+        downloaded += n as u64;
+        progress(downloaded, total.unwrap_or(0));
+    }
+
+    match total {
+        Some(total) if downloaded < total => {
+            Err(format!("下载不完整: {} / {} 字节", downloaded, total))
+        }
+        Some(total) if downloaded > total => {
+            let _ = fs::remove_file(part);
+            Err(format!("下载内容超出预期大小: {} / {} 字节", downloaded, total))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn log_result(
+    method: &str,
+    url: &str,
+    start: Instant,
+    result: &Result<ureq::Response, ureq::Error>,
+) {
+    let elapsed = start.elapsed();
+    match result {
+        Ok(resp) => verbose!("{} {} -> {} ({:?})", method, url, resp.status(), elapsed),
+        Err(ureq::Error::Status(code, _)) => {
+            verbose!("{} {} -> {} ({:?})", method, url, code, elapsed)
+        }
+        Err(ureq::Error::Transport(e)) => {
+            verbose!("{} {} -> {} ({:?})", method, url, e.kind(), elapsed)
+        }
+    }
+}
+
+/// 隐藏 URL 中的账号密码和疑似令牌的查询参数
+pub fn redact_url(url: &str) -> String {
+    let (base, query) = match url.split_once('?') {
+        Some((b, q)) => (b, Some(q)),
+        None => (url, None),
+    };
+
+    let mut out = base.to_string();
+    if let Some((scheme, rest)) = base.split_once("://") {
+        let authority_end = rest.find('/').unwrap_or(rest.len());
+        if let Some(at) = rest[..authority_end].rfind('@') {
+            out = format!("{}://***@{}", scheme, &rest[at + 1..]);
+        }
+    }
+
+    if let Some(query) = query {
+        let pairs: Vec<String> = query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((key, _)) if is_secret_key(key) => format!("{}=***", key),
+                _ => pair.to_string(),
+            })
+            .collect();
+        out.push('?');
+        out.push_str(&pairs.join("&"));
+    }
+    out
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    ["token", "auth", "key", "secret", "password", "sign"]
+        .iter()
+        .any(|s| key.contains(s))
+}
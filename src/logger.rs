@@ -0,0 +1,327 @@
+//! 启动器自身的日志输出
+//!
+//! 本模块在 main.rs 中最先声明，其中的 `println!` / `eprintln!` 会覆盖标准库的同名宏：
+//! 输出到终端的同时写入 `logs/llbot-cli.log`。`--json` 模式下 `println!` 改为输出到 stderr，
+//! 把 stdout 留给 [`crate::events`]。`--quiet` 下 `println!` 不再输出到终端，但仍按
+//! `--log-level` 写入日志文件；二维码、登录结果等必须让用户看到的内容使用 `notice!`。
+//! `--daemon` 的后台进程的 stdout / stderr 本身就是日志文件，各宏只写入一次。
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+static STRICT: AtomicBool = AtomicBool::new(false);
+/// `--daemon` 启动的后台进程：stdout / stderr 已重定向到日志文件，不再重复输出
+static DETACHED: AtomicBool = AtomicBool::new(false);
+/// 子进程启动后 `--strict` 不再直接退出，改为置位 [`STRICT_FAILED`] 由主循环结束子进程后退出
+static STRICT_DEFERRED: AtomicBool = AtomicBool::new(false);
+static STRICT_FAILED: AtomicBool = AtomicBool::new(false);
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+const LOG_FILE_NAME: &str = "llbot-cli.log";
+const MAX_LOG_SIZE: u64 = 5 * 1024 * 1024;
+/// 包括当前文件在内保留的日志文件数
+const KEEP_LOG_FILES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+}
+
+impl Level {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "off" => Some(Level::Off),
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Level::Off => "OFF",
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+struct LogFile {
+    file: File,
+    path: PathBuf,
+    size: u64,
+}
+
+pub fn set_verbose(enabled: bool) {
+    VERBOSE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+pub fn set_quiet(enabled: bool) {
+    QUIET.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+pub fn set_detached(enabled: bool) {
+    DETACHED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_detached() -> bool {
+    DETACHED.load(Ordering::Relaxed)
+}
+
+pub fn set_strict(enabled: bool) {
+    STRICT.store(enabled, Ordering::Relaxed);
+}
+
+/// 日志文件路径 `<exe_dir>/logs/llbot-cli.log`
+pub fn file_path(exe_dir: &Path) -> PathBuf {
+    exe_dir.join("logs").join(LOG_FILE_NAME)
+}
+
+/// 打开 `<exe_dir>/logs/llbot-cli.log`，之后的输出按 `level` 过滤后写入
+pub fn init_file(exe_dir: &Path, level: Level) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+    if level == Level::Off {
+        return;
+    }
+
+    let path = file_path(exe_dir);
+    let dir = exe_dir.join("logs");
+    let opened = fs::create_dir_all(&dir).and_then(|_| {
+        OpenOptions::new().create(true).append(true).open(&path)
+    });
+    match opened {
+        Ok(file) => {
+            let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+            *LOG_FILE.lock().unwrap() = Some(LogFile { file, path, size });
+        }
+        Err(e) => ::std::eprintln!("警告: 无法写入日志文件 {}: {}", path.display(), e),
+    }
+}
+
+/// 写入一行日志，去掉颜色控制符并加上时间和级别前缀
+pub fn log_line(level: Level, message: &str) {
+    if level > current_level() || message.trim().is_empty() {
+        return;
+    }
+    let Ok(mut guard) = LOG_FILE.lock() else {
+        return;
+    };
+    let Some(log) = guard.as_mut() else {
+        return;
+    };
+
+    if log.size >= MAX_LOG_SIZE {
+        rotate(log);
+    }
+    let line = format!("{} [{}] {}\n", timestamp(), level.label(), strip_ansi(message));
+    if log.file.write_all(line.as_bytes()).is_ok() {
+        log.size += line.len() as u64;
+    }
+}
+
+fn current_level() -> Level {
+    match LOG_LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Off,
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        _ => Level::Debug,
+    }
+}
+
+/// llbot-cli.log -> .1 -> .2，最旧的文件被删除
+fn rotate(log: &mut LogFile) {
+    let numbered = |n: usize| log.path.with_extension(format!("log.{}", n));
+    let _ = fs::remove_file(numbered(KEEP_LOG_FILES - 1));
+    for n in (1..KEEP_LOG_FILES - 1).rev() {
+        let _ = fs::rename(numbered(n), numbered(n + 1));
+    }
+    let _ = fs::rename(&log.path, numbered(1));
+    if let Ok(file) = OpenOptions::new().create(true).append(true).open(&log.path) {
+        log.file = file;
+        log.size = 0;
+    }
+}
+
+fn strip_ansi(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1B' {
+            // 跳过 ESC [ ... 字母 形式的控制序列
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// UTC 时间，格式为 2024-01-02T03:04:05Z
+pub fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // 公历日期换算，参见 Howard Hinnant 的 civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// 输出警告；`--strict` 下视为错误并以非零状态码退出。子进程启动后可能在任意线程中调用，
+/// 此时只记录失败，由主循环结束子进程后退出，避免 PMHQ 和 QQ 成为孤儿进程
+pub fn warning(message: std::fmt::Arguments) {
+    let message = format!("警告: {}", message);
+    if !is_detached() {
+        ::std::eprintln!("{}", message);
+    }
+    log_line(Level::Warn, &message);
+    if !STRICT.load(Ordering::Relaxed) || STRICT_FAILED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let message = "错误: --strict 模式下警告视为错误，程序退出";
+    if !is_detached() {
+        ::std::eprintln!("{}", message);
+    }
+    log_line(Level::Error, message);
+    if !STRICT_DEFERRED.load(Ordering::SeqCst) {
+        crate::wait_exit(1);
+    }
+}
+
+/// 即将启动子进程，之后的 `--strict` 失败交给主循环处理
+pub fn defer_strict_exit() {
+    STRICT_DEFERRED.store(true, Ordering::SeqCst);
+}
+
+/// `--strict` 下是否已出现警告，需要结束子进程并退出
+pub fn strict_failed() -> bool {
+    STRICT_FAILED.load(Ordering::SeqCst)
+}
+
+/// 输出到 stdout，同时以 INFO 级别写入日志文件；`--json` 模式下改为输出到 stderr，
+/// `--quiet` 下只写入日志文件
+macro_rules! println {
+    () => {
+        if $crate::logger::is_quiet() || $crate::logger::is_detached() {
+        } else if $crate::events::enabled() {
+            ::std::eprintln!()
+        } else {
+            ::std::println!()
+        }
+    };
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        if $crate::logger::is_quiet() || $crate::logger::is_detached() {
+        } else if $crate::events::enabled() {
+            ::std::eprintln!("{}", message);
+        } else {
+            ::std::println!("{}", message);
+        }
+        $crate::logger::log_line($crate::logger::Level::Info, &message);
+    }};
+}
+
+/// 与 `println!` 相同但不受 `--quiet` 影响，用于二维码、登录结果等用户必须看到的输出
+macro_rules! notice {
+    () => {
+        if $crate::logger::is_detached() {
+        } else if $crate::events::enabled() {
+            ::std::eprintln!()
+        } else {
+            ::std::println!()
+        }
+    };
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        if $crate::logger::is_detached() {
+        } else if $crate::events::enabled() {
+            ::std::eprintln!("{}", message);
+        } else {
+            ::std::println!("{}", message);
+        }
+        $crate::logger::log_line($crate::logger::Level::Info, &message);
+    }};
+}
+
+/// 输出到 stderr，同时以 ERROR 级别写入日志文件
+macro_rules! eprintln {
+    () => {
+        if !$crate::logger::is_detached() {
+            ::std::eprintln!()
+        }
+    };
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        if !$crate::logger::is_detached() {
+            ::std::eprintln!("{}", message);
+        }
+        $crate::logger::log_line($crate::logger::Level::Error, &message);
+    }};
+}
+
+/// 仅在 `--verbose` 下输出到 stderr，日志级别为 debug 时写入日志文件
+#[macro_export]
+macro_rules! verbose {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        if $crate::logger::is_verbose() && !$crate::logger::is_detached() {
+            ::std::eprintln!("[verbose] {}", message);
+        }
+        $crate::logger::log_line($crate::logger::Level::Debug, &message);
+    }};
+}
+
+/// 输出警告，`--strict` 下直接退出
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::logger::warning(format_args!($($arg)*))
+    };
+}
@@ -1,40 +1,119 @@
 //! LLBot CLI - 启动器
 
+#[macro_use]
+mod logger;
+mod clean;
+mod color;
+mod completions;
+mod config;
+mod doctor;
+mod events;
+mod fsutil;
+mod http;
+mod pidfile;
 mod pmhq_client;
+mod qq_version;
 mod qrcode_display;
+mod status_server;
+mod systemd;
 mod updater;
 
 use command_group::{CommandGroup, GroupChild};
-use pmhq_client::PMHQClient;
-use qrcode_display::{print_qrcode_terminal, save_qrcode_image};
+use pmhq_client::{LoginState, PMHQClient, SelfInfo};
+use qrcode_display::{
+    log_qrcode, print_qrcode_terminal, render_qrcode_image, save_qrcode_ascii, save_qrcode_image,
+    save_qrcode_svg, QrStyle, SvgOptions,
+};
+use status_server::StatusServer;
 use std::env;
-use std::fs::{self, File};
-use std::io::Write;
-use std::net::TcpListener;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const DEFAULT_PORT: u16 = 13000;
 const PORT_RANGE_END: u16 = 14000;
+/// 未扫码登录时等待的默认时长，超时后结束 PMHQ 并退出
+const DEFAULT_LOGIN_TIMEOUT: Duration = Duration::from_secs(300);
+/// 登录等 PMHQ 请求的默认超时，可用 `--request-timeout` 修改
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// SSE 连接的超时为请求超时的倍数，默认 300 秒
+const SSE_TIMEOUT_FACTOR: u32 = 30;
+/// 启动后等待 PMHQ API 可用的最长时间
+const PMHQ_READY_TIMEOUT: Duration = Duration::from_secs(60);
+/// 二维码每隔多少秒刷新一次
+const QR_REFRESH_INTERVAL: u32 = 120;
+/// 请求二维码失败后多少秒重试
+const QR_RETRY_INTERVAL: u32 = 5;
+/// 连续多少次请求二维码失败后提示检查 PMHQ
+const QR_FAILURE_THRESHOLD: u32 = 3;
+/// Ctrl+C 后等待子进程正常退出的最长时间
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// package.json 未声明 `engines.node` 时要求的最低 node 主版本
+const MIN_NODE_MAJOR: u32 = 18;
+/// 登录事件后账号信息通常稍晚才就绪，`--login-settle` 较短时仍至少重试的次数和间隔
+const SELF_INFO_RETRIES: u32 = 5;
+const SELF_INFO_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// 登录 webhook 请求的超时时间
+const LOGIN_WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// `--kill-qq` 结束 QQ 后等待其退出的最长时间
+const QQ_EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// `--daemon` 设置给后台进程的环境变量，表示其 stdout / stderr 已重定向到日志文件
+const DAEMON_ENV: &str = "LLBOT_DAEMON";
+/// 退出码：0 为成功，1 为参数错误等其他失败，pmhq 退出时沿用它的退出码
+const EXIT_PMHQ_MISSING: i32 = 2;
+/// node 或 llbot.js 缺失
+const EXIT_LLBOT_MISSING: i32 = 3;
+const EXIT_NO_PORT: i32 = 4;
+const EXIT_QQ_MISSING: i32 = 5;
+/// 旧版文件迁移完成后在程序目录写入的标记文件
+const MIGRATION_MARKER: &str = ".migrated";
+/// 收到 SIGHUP 后置位，由二维码刷新线程取走后立即请求新的二维码
+static QR_REFRESH_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// `--foreground`：在已有的终端中运行，退出前不等待按键
+static FOREGROUND: AtomicBool = AtomicBool::new(false);
+/// 统计异常退出次数的时间窗口
+const RESTART_WINDOW: Duration = Duration::from_secs(600);
+const RESTART_BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// 窗口内默认最多重启的次数
+const DEFAULT_MAX_RESTARTS: u32 = 5;
+/// 内置的 QQ 安装包地址，官方删除该版本后可用 `--qq-url` 或 llbot.toml 的 `qq_url` 覆盖
 const QQ_DOWNLOAD_URL: &str = "https://dldir1v6.qq.com/qqfile/qq/QQNT/c50d6326/QQ9.9.22.40768_x64.exe";
+/// `QQ_DOWNLOAD_URL` 安装包的 SHA-256，更换内置地址时必须同步更新；
+/// 为 None 时内置地址同样需要 `--qq-sha256` 才会安装
+const QQ_SHA256: Option<&str> = None;
+
+/// 自动安装 QQ 时下载的安装包
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+struct QqInstaller {
+    url: String,
+    /// 期望的 SHA-256（十六进制），None 表示无法校验
+    sha256: Option<String>,
+    /// `--qq-no-verify`：自定义地址没有 SHA-256 时仍然运行安装包，内置地址始终校验
+    allow_unverified: bool,
+}
 
 fn should_show_terminal_qrcode(exe_dir: &Path, args: &[String]) -> bool {
-    if cfg!(not(target_os = "windows")) {
-        return true;
-    }
-    
+    cfg!(not(target_os = "windows")) || is_headless(exe_dir, args)
+}
+
+/// 命令行有 `--headless`，或 pmhq_config.json 中 `headless` 为 true
+fn is_headless(exe_dir: &Path, args: &[String]) -> bool {
     if args.iter().any(|a| a == "--headless") {
         return true;
     }
     
     let config_path = exe_dir.join("bin/pmhq/pmhq_config.json");
     if let Ok(content) = fs::read_to_string(&config_path) {
-        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
-            return json.get("headless").and_then(|v| v.as_bool()).unwrap_or(false);
+        match serde_json::from_str::<serde_json::Value>(&content) {
+            Ok(json) => return json.get("headless").and_then(|v| v.as_bool()).unwrap_or(false),
+            Err(e) => warning!("解析 {} 失败，按非无头模式处理: {}", config_path.display(), e),
         }
     }
     false
@@ -48,7 +127,8 @@ fn get_exe_name(base: &str) -> String {
     }
 }
 
-fn find_pmhq_exe(exe_dir: &Path) -> Option<PathBuf> {
+/// 按优先级返回 pmhq 可执行文件的候选路径：先平台专用文件名，再通用文件名
+fn pmhq_candidates(exe_dir: &Path) -> Vec<PathBuf> {
     let pmhq_dir = exe_dir.join("bin/pmhq");
     
     let platform_arch = if cfg!(target_os = "windows") {
@@ -71,19 +151,93 @@ fn find_pmhq_exe(exe_dir: &Path) -> Option<PathBuf> {
         ""
     };
     
+    let mut candidates = Vec::new();
     if !platform_arch.is_empty() {
-        let arch_specific = pmhq_dir.join(get_exe_name(&format!("pmhq-{}", platform_arch)));
-        if arch_specific.exists() {
-            return Some(arch_specific);
-        }
+        candidates.push(pmhq_dir.join(get_exe_name(&format!("pmhq-{}", platform_arch))));
     }
-    
-    let generic = pmhq_dir.join(get_exe_name("pmhq"));
-    if generic.exists() {
-        return Some(generic);
+    candidates.push(pmhq_dir.join(get_exe_name("pmhq")));
+    candidates
+}
+
+fn find_pmhq_exe(exe_dir: &Path) -> Option<PathBuf> {
+    pmhq_candidates(exe_dir).into_iter().find(|p| p.exists())
+}
+
+/// 没有平台专用的 pmhq 而回退到通用文件名时发出警告
+fn warn_pmhq_fallback(exe_dir: &Path, pmhq_exe: &Path) {
+    let candidates = pmhq_candidates(exe_dir);
+    if candidates.len() > 1 && candidates.last().map(PathBuf::as_path) == Some(pmhq_exe) {
+        warning!(
+            "未找到 {}，回退使用 {}",
+            candidates[0].display(),
+            pmhq_exe.display()
+        );
     }
-    
-    None
+}
+
+/// 回退使用的通用 pmhq 可能是其他架构的文件，启动前检查文件头，避免只看到含糊的 exec 错误
+fn warn_pmhq_arch(pmhq_exe: &Path) {
+    match doctor::binary_arch(pmhq_exe) {
+        Ok(Some(arch)) if arch != std::env::consts::ARCH => warning!(
+            "{} 是 {} 架构的程序，与当前系统 {} 不符，可能无法启动；请运行 `llbot --reinstall` 下载对应架构的 PMHQ",
+            pmhq_exe.display(),
+            arch,
+            std::env::consts::ARCH
+        ),
+        Ok(Some(arch)) => verbose!("pmhq 架构: {}", arch),
+        Ok(None) => verbose!("无法识别 {} 的文件格式，跳过架构检查", pmhq_exe.display()),
+        Err(e) => verbose!("读取 {} 失败，跳过架构检查: {}", pmhq_exe.display(), e),
+    }
+}
+
+/// `--print-paths`：列出启动器查找的所有路径及其是否存在，不启动任何进程
+fn print_paths(exe_dir: &Path, node_path: &Path, qrcode_path: &Path) {
+    let mark = |path: &Path| {
+        if path.exists() {
+            color::green("存在")
+        } else {
+            color::yellow("缺失")
+        }
+    };
+    let llbot_dir = exe_dir.join("bin/llbot");
+
+    println!("程序目录:     {}", exe_dir.display());
+    let matched = find_pmhq_exe(exe_dir);
+    for candidate in pmhq_candidates(exe_dir) {
+        let tag = if matched.as_ref() == Some(&candidate) { " (使用)" } else { "" };
+        println!("pmhq:         {} [{}]{}", candidate.display(), mark(&candidate), tag);
+    }
+    println!("node:         {} [{}]", node_path.display(), mark(node_path));
+    let llbot_js = llbot_dir.join("llbot.js");
+    println!("llbot.js:     {} [{}]", llbot_js.display(), mark(&llbot_js));
+    let data_dir = llbot_dir.join("data");
+    println!("数据目录:     {} [{}]", data_dir.display(), mark(&data_dir));
+    println!("二维码文件:   {} [{}]", qrcode_path.display(), mark(qrcode_path));
+}
+
+/// `--print-config`：输出解析后的路径和设置，便于反馈问题时粘贴，不启动任何进程
+fn print_config(exe_dir: &Path, node_path: &Path, args: &[String], ports: &str) {
+    let or_missing = |path: Option<PathBuf>| {
+        path.map(|p| p.display().to_string()).unwrap_or_else(|| color::yellow("未找到"))
+    };
+    let existing = |path: PathBuf| path.exists().then_some(path);
+    let llbot_dir = exe_dir.join("bin/llbot");
+    let qq_path = args
+        .iter()
+        .find_map(|a| a.strip_prefix("--qq-path="))
+        .map(str::to_string)
+        .or_else(find_qq_path);
+
+    println!("程序目录:   {}", exe_dir.display());
+    println!("pmhq:       {}", or_missing(find_pmhq_exe(exe_dir)));
+    println!("node:       {}", or_missing(existing(node_path.to_path_buf())));
+    println!("llbot.js:   {}", or_missing(existing(llbot_dir.join("llbot.js"))));
+    println!("QQ:         {}", or_missing(qq_path.map(PathBuf::from)));
+    println!("端口:       {}", ports);
+    println!("无头模式:   {}", if is_headless(exe_dir, args) { "是" } else { "否" });
+    println!("CLI 版本:   {}", updater::cli_version());
+    println!("PMHQ 版本:  {}", updater::get_local_version(exe_dir, "pmhq"));
+    println!("LLBot 版本: {}", updater::get_local_version(exe_dir, "llbot"));
 }
 
 fn main() {
@@ -92,94 +246,512 @@ fn main() {
         .and_then(|p| p.parent().map(|p| p.to_path_buf()))
         .unwrap_or_else(|| PathBuf::from("."));
 
-    let args: Vec<String> = env::args().skip(1).collect();
+    if env::var_os(DAEMON_ENV).is_some() {
+        logger::set_detached(true);
+        // 不传给 PMHQ 及其启动的进程
+        env::remove_var(DAEMON_ENV);
+    }
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // 最先处理，之后的任何错误退出都要按它决定是否等待按键
+    FOREGROUND.store(take_flag(&mut args, "--foreground"), Ordering::Relaxed);
+    // llbot.toml 中的默认值先转换为参数，命令行参数优先
+    let profile = take_value(&mut args, "--profile=");
+    let launcher_config = config::LauncherConfig::load(&exe_dir).unwrap_or_else(|e| {
+        eprintln!("错误: {}", e);
+        eprintln!("配置优先级: 命令行参数 > {} > 内置默认值", config::CONFIG_FILE);
+        eprintln!("请修正或删除该文件后重试");
+        wait_exit(1);
+    });
+    if let Err(e) = launcher_config.apply_to_args(&mut args, profile.as_deref()) {
+        eprintln!("错误: {}", e);
+        wait_exit(1);
+    }
+    if let Some(proxy) = take_value(&mut args, "--proxy=") {
+        if let Err(e) = http::set_proxy(&proxy) {
+            eprintln!("错误: {}", e);
+            wait_exit(1);
+        }
+    }
+    if let Some(ref registry) = launcher_config.registry_mirror {
+        updater::set_preferred_registry(registry);
+    }
+    updater::set_allow_prerelease(take_flag(&mut args, "--allow-prerelease"));
+    if let Some(v) = take_value(&mut args, "--mirror-timeout=") {
+        let timeout = parse_secs("--mirror-timeout", &v);
+        if timeout.is_zero() {
+            eprintln!("错误: --mirror-timeout 需要大于 0 的秒数");
+            wait_exit(1);
+        }
+        updater::set_mirror_timeout(timeout);
+    }
+    if let Some(registry) = take_value(&mut args, "--registry=") {
+        if let Err(e) = updater::set_registry(&registry) {
+            eprintln!("错误: {}", e);
+            wait_exit(1);
+        }
+    }
+    // 启动器自身的参数，不转发给 pmhq
+    logger::set_verbose(take_flag(&mut args, "--verbose"));
+    logger::set_strict(take_flag(&mut args, "--strict"));
+    // 只对启动流程生效，查询、更新类命令的输出不受影响
+    let quiet = take_flag(&mut args, "--quiet");
+    let log_level = take_value(&mut args, "--log-level=")
+        .map(|v| {
+            logger::Level::parse(&v).unwrap_or_else(|| {
+                eprintln!("错误: --log-level 只支持 off、error、warn、info 或 debug: {}", v);
+                wait_exit(1);
+            })
+        })
+        .unwrap_or(logger::Level::Info);
+    logger::init_file(&exe_dir, log_level);
+    if let Some(path) = take_value(&mut args, "--ca-cert=") {
+        if let Err(e) = http::set_ca_cert(Path::new(&path)) {
+            eprintln!("错误: {}", e);
+            wait_exit(1);
+        }
+    }
+    if take_flag(&mut args, "--force-color") {
+        color::force(true);
+    }
+    if take_flag(&mut args, "--no-color") {
+        color::force(false);
+    }
+    if let Some(mode) = take_value(&mut args, "--color=") {
+        if !color::set_mode(&mode) {
+            eprintln!("错误: --color 只支持 auto、always 或 never: {}", mode);
+            wait_exit(1);
+        }
+    }
+
+    if let Some(shell) = take_value(&mut args, "--completions=") {
+        match completions::generate(&shell, "llbot") {
+            Some(script) => {
+                print!("{}", script);
+                std::process::exit(0);
+            }
+            None => {
+                eprintln!(
+                    "错误: --completions 只支持 {}: {}",
+                    completions::SHELLS.join("、"),
+                    shell
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    events::set_enabled(take_flag(&mut args, "--json"));
+    // --json 模式下 stdout 的每一行都是 JSON，PMHQ 的输出同样按 NDJSON 转发
+    let json_logs = take_flag(&mut args, "--json-logs") || events::enabled();
+    let ephemeral_port = take_flag(&mut args, "--ephemeral-port");
+    // 只在指定了 --bind 时把地址转发给 pmhq，默认行为不变
+    let bind = take_value(&mut args, "--bind=").map(|v| {
+        v.parse::<IpAddr>().unwrap_or_else(|_| {
+            eprintln!("错误: --bind 需要一个 IPv4 或 IPv6 地址，例如 0.0.0.0 或 ::1: {}", v);
+            wait_exit(1);
+        })
+    });
+    let bind_addr = bind.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    pmhq_client::set_bind_address(bind_addr);
+    let fixed_port = take_value(&mut args, "--port=").map(|v| {
+        v.parse::<u16>().ok().filter(|&p| p != 0).unwrap_or_else(|| {
+            eprintln!("错误: --port 需要 1-65535 之间的端口号: {}", v);
+            wait_exit(1);
+        })
+    });
+    let port_range = take_value(&mut args, "--port-range=")
+        .map(|v| {
+            parse_port_range(&v).unwrap_or_else(|| {
+                eprintln!("错误: --port-range 格式应为 START-END，例如 13000-14000: {}", v);
+                wait_exit(1);
+            })
+        })
+        .unwrap_or((DEFAULT_PORT, PORT_RANGE_END));
+    let request_timeout = take_value(&mut args, "--request-timeout=")
+        .map(|v| {
+            let timeout = parse_secs("--request-timeout", &v);
+            if timeout.is_zero() {
+                eprintln!("错误: --request-timeout 需要大于 0 的秒数");
+                wait_exit(1);
+            }
+            timeout
+        })
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT);
+    let watchdog_failures = take_value(&mut args, "--watchdog-failures=")
+        .map(|v| parse_count("--watchdog-failures", &v))
+        .unwrap_or(3);
+    let watchdog = take_value(&mut args, "--watchdog-interval=")
+        .map(|v| parse_secs("--watchdog-interval", &v))
+        .filter(|d| !d.is_zero())
+        .map(|interval| WatchdogOptions {
+            interval,
+            max_failures: watchdog_failures,
+            request_timeout,
+        });
+    let login_settle = take_value(&mut args, "--login-settle=")
+        .map(|v| parse_secs("--login-settle", &v))
+        .unwrap_or(Duration::ZERO);
+    // 0 表示不限制登录时间
+    let login_timeout = take_value(&mut args, "--login-timeout=")
+        .map(|v| parse_secs("--login-timeout", &v))
+        .unwrap_or(DEFAULT_LOGIN_TIMEOUT);
+    let login_timeout = Some(login_timeout).filter(|d| !d.is_zero());
+    let wait_login = take_flag(&mut args, "--wait-for-login");
+    let dump_sse = take_flag(&mut args, "--dump-sse");
+    let status_port = take_value(&mut args, "--status-port=").map(|v| {
+        v.parse::<u16>().ok().filter(|&p| p != 0).unwrap_or_else(|| {
+            eprintln!("错误: --status-port 需要 1-65535 之间的端口号: {}", v);
+            wait_exit(1);
+        })
+    });
+    let qr_style = match take_value(&mut args, "--qr-style=") {
+        Some(v) => QrStyle::parse(&v).unwrap_or_else(|| {
+            eprintln!("错误: --qr-style 只支持 block、invert 或 ansi: {}", v);
+            wait_exit(1);
+        }),
+        None => QrStyle::Block,
+    };
+    let qr_stdout_only = take_flag(&mut args, "--qr-stdout-only");
+    let login_webhook = take_value(&mut args, "--on-login-webhook=");
+    if let Some(ref url) = login_webhook {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            eprintln!("错误: --on-login-webhook 需要 http:// 或 https:// 开头的地址: {}", url);
+            wait_exit(1);
+        }
+    }
+    let qr_svg_module_size = take_value(&mut args, "--qr-svg-module-size=")
+        .map(|v| parse_count("--qr-svg-module-size", &v));
+    let qr_svg_no_quiet_zone = take_flag(&mut args, "--qr-svg-no-quiet-zone");
+    let qr_svg = take_flag(&mut args, "--qr-svg").then(|| {
+        let defaults = SvgOptions::default();
+        SvgOptions {
+            module_size: qr_svg_module_size.unwrap_or(defaults.module_size),
+            quiet_zone: !qr_svg_no_quiet_zone,
+        }
+    });
+    // 多账号：--account 作为快速登录账号转发给 pmhq，同时用于校验登录结果和区分数据目录
+    let account = take_value(&mut args, "--account=");
+    if let Some(ref uin) = account {
+        if uin.is_empty() || !uin.chars().all(|c| c.is_ascii_digit()) {
+            eprintln!("错误: --account 需要一个 QQ 号: {}", uin);
+            wait_exit(1);
+        }
+        if !args.iter().any(|a| a.starts_with("--qq=")) {
+            args.push(format!("--qq={}", uin));
+        }
+    }
+    let migrate_dry_run = take_flag(&mut args, "--migrate-dry-run");
+    let no_migrate = take_flag(&mut args, "--no-migrate");
+    let kill_qq = take_flag(&mut args, "--kill-qq");
+    // 追加给 node 和 llbot.js 的参数，可重复指定
+    let node_args = take_values(&mut args, "--node-arg=");
+    let mut llbot_args = take_values(&mut args, "--llbot-arg=");
+    let child_env = take_env_args(&mut args);
+    // llbot.js 的工作目录是 bin/llbot，相对路径按启动时的当前目录解析
+    let data_dir = take_value(&mut args, "--data-dir=").map(|v| {
+        std::path::absolute(&v).unwrap_or_else(|e| {
+            eprintln!("错误: --data-dir 路径无效: {}: {}", v, e);
+            wait_exit(1);
+        })
+    });
+    // 多账号时默认使用各账号自己的数据目录，同样要转发给 llbot.js，否则迁移过去的数据不会被读取
+    let data_dir = data_dir.or_else(|| {
+        account
+            .as_deref()
+            .map(|uin| default_data_dir(&exe_dir, Some(uin)))
+    });
+    if let Some(ref dir) = data_dir {
+        llbot_args.insert(0, format!("--data-dir={}", dir.display()));
+    }
+    let data_dir_or_default = data_dir
+        .clone()
+        .unwrap_or_else(|| default_data_dir(&exe_dir, None));
+    let qrcode_path = match take_value(&mut args, "--qrcode-path=") {
+        Some(v) => std::path::absolute(&v).unwrap_or_else(|e| {
+            eprintln!("错误: --qrcode-path 路径无效: {}: {}", v, e);
+            wait_exit(1);
+        }),
+        None => exe_dir.join("qrcode.png"),
+    };
+    let qq_sha256 = take_value(&mut args, "--qq-sha256=").map(|v| {
+        if v.len() != 64 || !v.chars().all(|c| c.is_ascii_hexdigit()) {
+            eprintln!("错误: --qq-sha256 需要 64 位十六进制的 SHA-256: {}", v);
+            wait_exit(1);
+        }
+        v.to_ascii_lowercase()
+    });
+    // 内置地址使用内置的校验值，自定义地址只能由 --qq-sha256 提供
+    let qq_no_verify = take_flag(&mut args, "--qq-no-verify");
+    let qq_installer = match take_value(&mut args, "--qq-url=") {
+        Some(url) => QqInstaller {
+            url,
+            sha256: qq_sha256,
+            allow_unverified: qq_no_verify,
+        },
+        None => QqInstaller {
+            url: QQ_DOWNLOAD_URL.to_string(),
+            sha256: qq_sha256.or(QQ_SHA256.map(str::to_string)),
+            allow_unverified: false,
+        },
+    };
+    let restart_max = take_value(&mut args, "--restart-max=")
+        .map(|v| parse_count("--restart-max", &v))
+        .unwrap_or(DEFAULT_MAX_RESTARTS);
+    let mut restart_policy =
+        take_flag(&mut args, "--restart-on-crash").then(|| RestartPolicy::new(restart_max));
+    let log_qr = take_flag(&mut args, "--log-qr");
+    // --node-path 指定的外部 node，未指定时使用 bin/llbot 下自带的
+    let node_path_arg = take_value(&mut args, "--node-path=").map(|v| {
+        std::path::absolute(&v).unwrap_or_else(|e| {
+            eprintln!("错误: --node-path 路径无效: {}: {}", v, e);
+            wait_exit(1);
+        })
+    });
+    let node_path = node_path_arg
+        .clone()
+        .unwrap_or_else(|| exe_dir.join("bin/llbot").join(get_exe_name("node")));
+    let qr_ascii_path = take_value(&mut args, "--export-qr-ascii=").map(|v| {
+        std::path::absolute(&v).unwrap_or_else(|e| {
+            eprintln!("错误: --export-qr-ascii 路径无效: {}: {}", v, e);
+            wait_exit(1);
+        })
+    });
+    let daemon = take_flag(&mut args, "--daemon");
+    if daemon {
+        if cfg!(target_os = "windows") {
+            eprintln!("错误: --daemon 仅支持 Linux 和 macOS，Windows 下请使用计划任务或服务管理器在后台运行");
+            wait_exit(1);
+        }
+        if log_level == logger::Level::Off {
+            eprintln!("错误: --daemon 的输出只写入日志文件，不能与 --log-level=off 一起使用");
+            wait_exit(1);
+        }
+    }
+    let login_token = take_value(&mut args, "--login-token=")
+        .or_else(|| env::var("LLBOT_LOGIN_TOKEN").ok())
+        .filter(|t| !t.is_empty());
+
+    if take_flag(&mut args, "--stop") {
+        stop_running_processes();
+    }
+
+    if take_flag(&mut args, "--rollback") {
+        match updater::rollback(&exe_dir) {
+            Ok(()) => wait_exit(0),
+            Err(e) => {
+                eprintln!("回滚失败: {}", e);
+                wait_exit(1);
+            }
+        }
+    }
+
+    if take_flag(&mut args, "--print-paths") {
+        print_paths(&exe_dir, &node_path, &qrcode_path);
+        std::process::exit(0);
+    }
+
+    if take_flag(&mut args, "--print-config") {
+        let ports = if ephemeral_port {
+            "由系统分配".to_string()
+        } else if let Some(port) = fixed_port {
+            port.to_string()
+        } else {
+            format!("{}-{}", port_range.0, port_range.1)
+        };
+        let ports = format!("{}（监听地址 {}）", ports, bind_addr);
+        print_config(&exe_dir, &node_path, &args, &ports);
+        std::process::exit(0);
+    }
+
+    if take_flag(&mut args, "--doctor") {
+        std::process::exit(doctor::run(&exe_dir, &node_path, bind_addr, port_range));
+    }
+    if take_flag(&mut args, "--clean") {
+        std::process::exit(clean::run(&exe_dir));
+    }
+
     let pmhq_exe = match find_pmhq_exe(&exe_dir) {
         Some(path) => path,
         None => {
             eprintln!("错误: 未找到 pmhq 可执行文件");
             eprintln!("请确保 bin/pmhq/ 目录下存在 pmhq 或 pmhq-<platform>-<arch> 文件");
-            wait_exit(1);
+            wait_exit(EXIT_PMHQ_MISSING);
         }
     };
+    warn_pmhq_fallback(&exe_dir, &pmhq_exe);
 
     // --help 直接转发给 pmhq
     if args.iter().any(|a| a == "--help" || a == "-h") {
+        if cfg!(not(target_os = "windows")) {
+            println!("启动器: 扫码登录前向 llbot 进程发送 SIGHUP（kill -HUP <PID>）可立即刷新二维码");
+            println!();
+        }
         let status = Command::new(&pmhq_exe).args(&args).status();
         std::process::exit(status.map(|s| s.code().unwrap_or(0)).unwrap_or(1));
     }
 
     // --version 先输出 CLI 版本，再转发给 pmhq
     if args.iter().any(|a| a == "--version" || a == "-v") {
-        println!("llbot-cli {}", env!("CARGO_PKG_VERSION"));
+        println!("llbot-cli {}", updater::cli_version());
         let status = Command::new(&pmhq_exe).args(&args).status();
         std::process::exit(status.map(|s| s.code().unwrap_or(0)).unwrap_or(1));
     }
 
-    // --update 检查并执行更新
-    if args.iter().any(|a| a == "--update") {
-        updater::run_update(&exe_dir);
+    // --update 检查并执行更新，--reinstall 强制重新下载 pmhq 和 llbot
+    // --check-update 只报告结果，供计划任务使用，不等待按键
+    if take_flag(&mut args, "--check-update") {
+        std::process::exit(updater::run_check_update(&exe_dir));
+    }
+    if take_flag(&mut args, "--list-versions") {
+        updater::run_list_versions(&exe_dir);
+        std::process::exit(0);
+    }
+
+    let update_only = take_value(&mut args, "--update=").map(|v| {
+        updater::parse_component(&v).unwrap_or_else(|| {
+            eprintln!("错误: --update 只支持 cli、pmhq 或 llbot: {}", v);
+            wait_exit(1);
+        })
+    });
+    let update_opts = updater::UpdateOptions {
+        reinstall: take_flag(&mut args, "--reinstall"),
+        background: take_flag(&mut args, "--background"),
+        no_verify: take_flag(&mut args, "--no-verify"),
+        only: update_only,
+    };
+    if let Some(spec) = take_value(&mut args, "--update-from=") {
+        if let Err(e) = updater::run_update_from(&exe_dir, &spec) {
+            eprintln!("错误: {}", e);
+            wait_exit(1);
+        }
+        wait_exit(0);
+    }
+    if let Some(spec) = take_value(&mut args, "--install-version=") {
+        if let Err(e) = updater::run_install_version(&exe_dir, &spec, update_opts) {
+            eprintln!("错误: {}", e);
+            wait_exit(1);
+        }
+        wait_exit(0);
+    }
+    if update_opts.reinstall || update_only.is_some() || args.iter().any(|a| a == "--update") {
+        updater::run_update(&exe_dir, update_opts);
         wait_exit(0);
     }
 
-    // 检查 QQ 路径
-    if cfg!(target_os = "windows") {
+    // 检查 QQ 路径，--no-qq-check 时完全跳过
+    let qq_path = if take_flag(&mut args, "--no-qq-check") {
+        None
+    } else {
         let qq_path_arg = args.iter()
             .find(|a| a.starts_with("--qq-path="))
             .map(|a| a.trim_start_matches("--qq-path=").to_string());
-        
+
         let qq_path_arg_invalid = qq_path_arg.as_ref()
             .map(|p| !Path::new(p).exists())
             .unwrap_or(false);
-        
+
         if qq_path_arg_invalid {
             eprintln!("错误: 指定的 QQ 路径不存在: {}", qq_path_arg.as_ref().unwrap());
+            if !cfg!(target_os = "windows") {
+                wait_exit(EXIT_QQ_MISSING);
+            }
         }
-        
-        let qq_path = if qq_path_arg_invalid { None } else { qq_path_arg.or_else(get_qq_path_from_registry) };
-        
-        if qq_path.is_none() || !qq_path.as_ref().map(|p| Path::new(p).exists()).unwrap_or(false) {
-            println!("未找到 QQ，是否下载并安装？(y/n)");
-            let mut input = String::new();
-            if std::io::stdin().read_line(&mut input).is_ok() {
-                if input.trim().eq_ignore_ascii_case("y") {
-                    if !download_and_install_qq() {
-                        eprintln!("QQ 下载安装失败");
-                        wait_exit(1);
+
+        let qq_path = if qq_path_arg_invalid { None } else { qq_path_arg.or_else(find_qq_path) };
+
+        match qq_path {
+            Some(ref path) => verbose!("QQ 路径: {}", path),
+            None if cfg!(target_os = "windows") => {
+                println!("未找到 QQ，是否下载并安装？(y/n)");
+                let mut input = String::new();
+                if std::io::stdin().read_line(&mut input).is_ok() {
+                    if input.trim().eq_ignore_ascii_case("y") {
+                        if !download_and_install_qq(&qq_installer) {
+                            eprintln!("QQ 下载安装失败");
+                            wait_exit(EXIT_QQ_MISSING);
+                        }
+                        println!("QQ 安装完成，请重新运行程序");
+                        wait_exit(0);
+                    } else {
+                        eprintln!("错误: 未找到 QQ，请安装 QQ 或使用 --qq-path 参数指定路径");
+                        wait_exit(EXIT_QQ_MISSING);
                     }
-                    println!("QQ 安装完成，请重新运行程序");
-                    wait_exit(0);
-                } else {
-                    eprintln!("错误: 未找到 QQ，请安装 QQ 或使用 --qq-path 参数指定路径");
-                    wait_exit(1);
                 }
             }
+            None => warning!("未在常见位置找到 QQ，如启动失败请使用 --qq-path 参数指定路径"),
         }
+        qq_path
+    };
+
+    if take_flag(&mut args, "--list-accounts") {
+        list_accounts(&pmhq_exe, &args, bind, request_timeout);
     }
 
-    migrate_old_files(&exe_dir);
+    if migrate_dry_run {
+        migrate_old_files(&exe_dir, &data_dir_or_default, true);
+        std::process::exit(0);
+    }
+    // 同一目录只允许运行一个实例，查询、更新类的参数已在上面处理并退出
+    logger::set_quiet(quiet);
+    if let Some(pid) = pidfile::running_instance(&exe_dir, account.as_deref()) {
+        eprintln!("已有实例在运行 (PID: {})", pid);
+        eprintln!("如需结束该实例，请运行 `llbot --stop`");
+        wait_exit(1);
+    }
+    #[cfg(not(target_os = "windows"))]
+    if daemon {
+        daemonize(&exe_dir, account.as_deref(), &qrcode_path);
+    }
+    pidfile::write(&exe_dir, account.as_deref());
+    #[cfg(not(target_os = "windows"))]
+    install_qr_refresh_signal();
+    if let Some(ref dir) = data_dir {
+        if let Err(e) = fs::create_dir_all(dir).and_then(|()| doctor::check_writable(dir)) {
+            eprintln!("错误: 数据目录不可写: {}: {}", dir.display(), e);
+            wait_exit(1);
+        }
+    }
+    // 保存失败时会改为在终端显示二维码，这里只提示
+    let qr_dirs = [
+        qrcode_path.parent().filter(|_| !qr_stdout_only),
+        qr_ascii_path.as_deref().and_then(Path::parent),
+    ];
+    for dir in qr_dirs.into_iter().flatten() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            warning!("无法创建二维码目录 {}: {}", dir.display(), e);
+        }
+    }
+    if no_migrate {
+        verbose!("已指定 --no-migrate，跳过旧版文件迁移");
+    } else {
+        migrate_old_files(&exe_dir, &data_dir_or_default, false);
+    }
+    updater::apply_pending_updates(&exe_dir);
 
     let llbot_dir = exe_dir.join("bin/llbot");
-    let node_exe = get_exe_name("node");
-    let node_path = llbot_dir.join(&node_exe);
-
-    if !node_path.exists() {
-        eprintln!(
-            "错误: 未找到 {}: {}",
-            node_exe,
-            node_path.display()
-        );
-        wait_exit(1);
+    if let Some(ref path) = node_path_arg {
+        // 外部的 node 不属于本程序，不自动修改其权限
+        if let Err(e) = check_executable(path) {
+            eprintln!("错误: --node-path 指定的 node 不可用: {}: {}", path.display(), e);
+            wait_exit(EXIT_LLBOT_MISSING);
+        }
+        verbose!("使用外部 node: {}", path.display());
+    } else if !node_path.exists() {
+        eprintln!("错误: 未找到 {}: {}", get_exe_name("node"), node_path.display());
+        eprintln!("也可以使用 --node-path 指定系统中已安装的 node");
+        wait_exit(EXIT_LLBOT_MISSING);
     }
 
     #[cfg(not(target_os = "windows"))]
-    {
+    if node_path_arg.is_none() {
         use std::os::unix::fs::PermissionsExt;
         if let Ok(metadata) = fs::metadata(&node_path) {
             let mut perms = metadata.permissions();
             if perms.mode() & 0o111 == 0 {
+                warning!("node 缺少执行权限，正在自动添加");
                 perms.set_mode(perms.mode() | 0o755);
                 if let Err(e) = fs::set_permissions(&node_path, perms) {
-                    eprintln!("警告: 设置 node 执行权限失败: {}", e);
+                    warning!("设置 node 执行权限失败: {}", e);
                 }
             }
         }
@@ -189,21 +761,49 @@ fn main() {
             "错误: 未找到 llbot.js: {}",
             llbot_dir.join("llbot.js").display()
         );
-        wait_exit(1);
+        wait_exit(EXIT_LLBOT_MISSING);
+    }
+    check_node_version(&node_path, &llbot_dir);
+    if let Some(ref path) = qq_path {
+        qq_version::check(Path::new(path), &llbot_dir);
     }
 
-    let port = find_available_port(DEFAULT_PORT, PORT_RANGE_END).unwrap_or_else(|| {
-        eprintln!("错误: 无法找到可用端口 ({}-{})", DEFAULT_PORT, PORT_RANGE_END);
-        wait_exit(1);
-    });
+    let port = if ephemeral_port {
+        os_assigned_port(bind_addr).unwrap_or_else(|| {
+            eprintln!("错误: 系统无法分配临时端口");
+            wait_exit(EXIT_NO_PORT);
+        })
+    } else if let Some(port) = fixed_port {
+        if TcpListener::bind((bind_addr, port)).is_err() {
+            eprintln!("错误: 端口 {} 已被占用", port);
+            wait_exit(EXIT_NO_PORT);
+        }
+        port
+    } else {
+        let (start, end) = port_range;
+        find_available_port(bind_addr, start, end).unwrap_or_else(|| {
+            eprintln!("错误: 无法找到可用端口 ({}-{})", start, end);
+            wait_exit(EXIT_NO_PORT);
+        })
+    };
 
-    println!("LLBot CLI 启动器");
-    println!("================");
-    println!("端口: {}", port);
-    println!();
+    if !events::enabled() {
+        println!("LLBot CLI 启动器");
+        println!("================");
+        println!("端口: {}", port);
+        println!();
+    }
+
+    if kill_qq {
+        kill_running_qq();
+    }
+    warn_pmhq_arch(&pmhq_exe);
 
     let mut cmd = Command::new(&pmhq_exe);
     cmd.arg("--port").arg(port.to_string());
+    if let Some(addr) = bind {
+        cmd.arg("--host").arg(addr.to_string());
+    }
     
     if !args.is_empty() {
         cmd.args(&args);
@@ -214,88 +814,225 @@ fn main() {
         .arg("--sub-cmd")
         .arg(&node_path)
         .arg("--enable-source-maps")
+        .args(&node_args)
         .arg("llbot.js")
         .arg("--")
-        .arg(format!("--pmhq-port={}", port));
+        .arg(format!("--pmhq-port={}", port))
+        .args(&llbot_args);
 
-    let mut child: GroupChild = match cmd
-        .stdin(Stdio::null())
+    // 子进程本就继承启动器的环境变量；--proxy 只对启动器生效，这里同样交给子进程
+    if let Some(proxy) = http::proxy_arg() {
+        for key in ["HTTP_PROXY", "HTTPS_PROXY"] {
+            if !child_env.iter().any(|(k, _)| k.eq_ignore_ascii_case(key)) {
+                cmd.env(key, proxy);
+            }
+        }
+    }
+    cmd.envs(child_env.iter().map(|(k, v)| (k, v)));
+
+    cmd.stdin(Stdio::null())
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .group_spawn()
-    {
-        Ok(child) => child,
-        Err(e) => {
+        .stderr(Stdio::piped());
+    let spawn_child = |cmd: &mut Command| -> GroupChild {
+        cmd.group_spawn().unwrap_or_else(|e| {
             eprintln!("启动 pmhq 失败: {}", e);
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+            ) {
+                report_removed_binaries(&[&pmhq_exe, &node_path]);
+                wait_exit(EXIT_PMHQ_MISSING);
+            }
             wait_exit(1);
-        }
+        })
     };
+    log_command(&cmd);
+    logger::defer_strict_exit();
+    let mut child = spawn_child(&mut cmd);
+
+    events::emit("started", serde_json::json!({ "port": port, "pid": child.id() }));
+    systemd::status(&format!("PMHQ 已启动，端口 {}", port));
 
     let child_arc: Arc<Mutex<Option<GroupChild>>> = Arc::new(Mutex::new(None));
     let child_for_handler = child_arc.clone();
+    let ctrl_c_count = Arc::new(AtomicU32::new(0));
+    let ctrl_c_for_handler = ctrl_c_count.clone();
     
+    // 第一次 Ctrl+C 交给主循环优雅停止，第二次立即强制结束
     ctrlc::set_handler(move || {
+        if ctrl_c_for_handler.fetch_add(1, Ordering::SeqCst) == 0 {
+            return;
+        }
         if let Ok(mut guard) = child_for_handler.lock() {
             if let Some(ref mut c) = *guard {
                 let _ = c.kill();
             }
         }
+        pidfile::release();
+        events::emit("exit", serde_json::json!({ "code": 0 }));
         std::process::exit(0);
     })
     .ok();
 
-    let stdout = child.inner().stdout.take();
-    let stderr = child.inner().stderr.take();
+    // 两个读取线程把整行送入同一个有界通道，由单个写线程输出，保证行不被交错打断
+    let (line_tx, line_rx) = mpsc::sync_channel::<(ChildStream, String)>(1024);
+    // 端口检测和实际绑定之间可能被其他进程抢占，以 PMHQ 输出中报告的端口为准
+    let pmhq_port = Arc::new(AtomicU16::new(port));
+    let bound_port = pmhq_port.clone();
+    thread::spawn(move || {
+        for (stream, line) in line_rx {
+            if let Some(actual) = parse_bound_port(&line) {
+                let previous = bound_port.swap(actual, Ordering::Relaxed);
+                if previous != actual {
+                    println!("PMHQ 实际监听的端口为 {}（请求的是 {}），已改用该端口", actual, previous);
+                }
+            }
+            match stream {
+                ChildStream::Stdout => {
+                    forward_line(&mut std::io::stdout().lock(), stream, &line, json_logs)
+                }
+                ChildStream::Stderr => {
+                    forward_line(&mut std::io::stderr().lock(), stream, &line, json_logs)
+                }
+            }
+        }
+    });
+    attach_output(&mut child, &line_tx);
 
     // 把 child 移入 Arc，供 ctrlc handler 使用
     *child_arc.lock().unwrap() = Some(child);
     let child_for_wait = child_arc.clone();
 
-    if let Some(stdout) = stdout {
-        thread::spawn(move || {
-            use std::io::{BufRead, BufReader};
-            let reader = BufReader::new(stdout);
-            let mut out = std::io::stdout().lock();
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    let _ = writeln!(out, "{}", line);
-                    let _ = out.flush();
-                }
-            }
-        });
-    }
+    let login_timed_out = Arc::new(AtomicBool::new(false));
+    let login_opts = LoginOptions {
+        qrcode_path,
+        show_terminal_qr: (qr_stdout_only || should_show_terminal_qrcode(&exe_dir, &args))
+            && !events::enabled(),
+        save_image: !qr_stdout_only,
+        qr_style,
+        qr_svg,
+        log_qr,
+        qr_ascii_path,
+        settle_delay: login_settle,
+        account,
+        webhook: login_webhook,
+        request_timeout,
+        timeout: login_timeout,
+        timed_out: login_timed_out.clone(),
+        cancelled: Arc::new(AtomicBool::new(false)),
+    };
+    let mut logged_in = Arc::new(AtomicBool::new(false));
+    let mut login_cancelled = login_opts.cancelled.clone();
 
-    if let Some(stderr) = stderr {
-        thread::spawn(move || {
-            use std::io::{BufRead, BufReader};
-            let reader = BufReader::new(stderr);
-            let mut err = std::io::stderr().lock();
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    let _ = writeln!(err, "{}", line);
-                    let _ = err.flush();
-                }
+    let status_server = status_port.map(|listen| {
+        let started = StatusServer::start(
+            listen,
+            pmhq_port.clone(),
+            child_arc.clone(),
+            logged_in.clone(),
+            request_timeout,
+        );
+        let server = started.unwrap_or_else(|e| {
+            eprintln!("错误: 无法在 127.0.0.1:{} 上启动状态接口: {}", listen, e);
+            if let Some(ref mut c) = *child_arc.lock().unwrap() {
+                let _ = c.kill();
             }
+            wait_exit(1);
         });
-    }
-
-    let logged_in = Arc::new(AtomicBool::new(false));
-    let qrcode_path = exe_dir.join("qrcode.png");
-    let show_terminal_qr = should_show_terminal_qrcode(&exe_dir, &args);
+        println!("状态接口: http://127.0.0.1:{}/status", listen);
+        server
+    });
 
-    start_login_listener(port, logged_in.clone(), qrcode_path, show_terminal_qr);
+    start_login(&pmhq_port, login_token.clone(), logged_in.clone(), login_opts.clone());
+    if let Some(opts) = watchdog {
+        start_watchdog(&pmhq_port, child_arc.clone(), opts);
+    }
+    if dump_sse {
+        let client = PMHQClient::with_shared_port(pmhq_port.clone());
+        thread::spawn(move || client.dump_sse());
+    }
+    if wait_login {
+        wait_for_login(&logged_in, &login_timed_out, &ctrl_c_count, &child_for_wait);
+    }
 
-    // 等待子进程结束
+    // 等待子进程结束，--restart-on-crash 时异常退出后重新启动
+    let mut shutdown_deadline: Option<Instant> = None;
+    let mut exit_code = None;
     loop {
         thread::sleep(Duration::from_millis(100));
+        if shutdown_deadline.is_none() && ctrl_c_count.load(Ordering::SeqCst) > 0 {
+            println!();
+            println!(
+                "正在停止 pmhq（最多等待 {} 秒），再次按 Ctrl+C 强制退出...",
+                SHUTDOWN_TIMEOUT.as_secs()
+            );
+            systemd::stopping();
+            if let Ok(mut guard) = child_for_wait.lock() {
+                if let Some(ref mut c) = *guard {
+                    terminate_gracefully(c);
+                }
+            }
+            shutdown_deadline = Some(Instant::now() + SHUTDOWN_TIMEOUT);
+        }
+        if shutdown_deadline.is_some_and(|d| Instant::now() >= d) {
+            eprintln!("pmhq 未在 {} 秒内退出，强制结束", SHUTDOWN_TIMEOUT.as_secs());
+            if let Ok(mut guard) = child_for_wait.lock() {
+                if let Some(ref mut c) = *guard {
+                    let _ = c.kill();
+                }
+            }
+            break;
+        }
+        // 登录超时，或 --strict 下其他线程发出了警告
+        if login_timed_out.load(Ordering::Relaxed) || logger::strict_failed() {
+            if let Ok(mut guard) = child_for_wait.lock() {
+                if let Some(ref mut c) = *guard {
+                    let _ = c.kill();
+                }
+            }
+            wait_exit(1);
+        }
+        // 登录成功说明已恢复正常，重新累计崩溃次数
+        if logged_in.load(Ordering::Relaxed) && !login_cancelled.load(Ordering::Relaxed) {
+            if let Some(ref mut policy) = restart_policy {
+                policy.reset();
+            }
+        }
+
+        let mut restart_after = None;
         if let Ok(mut guard) = child_for_wait.lock() {
             if let Some(ref mut c) = *guard {
                 match c.try_wait() {
                     Ok(Some(status)) => {
-                        if !status.success() {
-                            eprintln!("pmhq 退出，状态码: {:?}", status.code());
+                        exit_code = status.code();
+                        if let Some(ref server) = status_server {
+                            server.state.set_running(false);
                         }
-                        break;
+                        if shutdown_deadline.is_some() {
+                            println!("pmhq 已停止");
+                            break;
+                        }
+                        if status.success() {
+                            break;
+                        }
+                        eprintln!("pmhq 退出，状态码: {:?}", status.code());
+                        report_removed_binaries(&[&pmhq_exe, &node_path]);
+                        let Some(ref mut policy) = restart_policy else {
+                            break;
+                        };
+                        match policy.on_crash() {
+                            Some(delay) => restart_after = Some(delay),
+                            None => {
+                                eprintln!(
+                                    "pmhq 在 {} 分钟内已异常退出 {} 次，不再重启",
+                                    RESTART_WINDOW.as_secs() / 60,
+                                    policy.crashes.len()
+                                );
+                                break;
+                            }
+                        }
+                        // 结束残留的子进程（QQ、node），释放端口
+                        let _ = c.kill();
                     }
                     Ok(None) => {}
                     Err(e) => {
@@ -307,30 +1044,510 @@ fn main() {
                 break;
             }
         }
+
+        let Some(delay) = restart_after else {
+            continue;
+        };
+        // 停止旧的登录监听，避免它连上新进程后重复处理二维码
+        login_cancelled.store(true, Ordering::Relaxed);
+        logged_in = Arc::new(AtomicBool::new(false));
+        if let Some(ref server) = status_server {
+            server.state.set_running(false);
+            server.state.set_logged_in(logged_in.clone());
+        }
+
+        println!("{} 秒后重新启动 pmhq...", delay.as_secs());
+        systemd::status(&format!("PMHQ 异常退出，{} 秒后重新启动", delay.as_secs()));
+        let resume_at = Instant::now() + delay;
+        while Instant::now() < resume_at && ctrl_c_count.load(Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(100));
+        }
+        // 等待重启时按 Ctrl+C 与主动停止相同，视为正常退出
+        if ctrl_c_count.load(Ordering::SeqCst) > 0 {
+            println!("已取消重启");
+            exit_code = Some(0);
+            break;
+        }
+
+        let mut child = spawn_child(&mut cmd);
+        events::emit("started", serde_json::json!({ "port": port, "pid": child.id() }));
+        systemd::status(&format!("PMHQ 已重新启动，端口 {}", port));
+        attach_output(&mut child, &line_tx);
+        *child_for_wait.lock().unwrap() = Some(child);
+
+        login_cancelled = Arc::new(AtomicBool::new(false));
+        let opts = LoginOptions {
+            cancelled: login_cancelled.clone(),
+            ..login_opts.clone()
+        };
+        start_login(&pmhq_port, login_token.clone(), logged_in.clone(), opts);
+        if let Some(ref server) = status_server {
+            server.state.set_running(true);
+        }
     }
+    // 主动停止时视为正常退出，否则沿用 pmhq 的退出码；被信号结束时没有退出码
+    let code = if shutdown_deadline.is_some() { 0 } else { exit_code.unwrap_or(1) };
+    if let Some(server) = status_server {
+        server.shutdown();
+    }
+    pidfile::release();
+    events::emit("exit", serde_json::json!({ "code": code }));
+    std::process::exit(code);
 }
 
-fn start_login_listener(
-    port: u16,
-    logged_in: Arc<AtomicBool>,
-    qrcode_path: PathBuf,
-    show_terminal_qr: bool,
+/// `--wait-for-login`：登录成功前不进入等待子进程的循环。登录超时或按下 Ctrl+C 时返回，
+/// 由后续的循环结束 pmhq；pmhq 在登录前退出时直接以非零状态码退出，不会按
+/// `--restart-on-crash` 重启
+fn wait_for_login(
+    logged_in: &AtomicBool,
+    timed_out: &AtomicBool,
+    ctrl_c_count: &AtomicU32,
+    child: &Mutex<Option<GroupChild>>,
 ) {
-    thread::spawn(move || {
-        let client = PMHQClient::new(port).with_timeout(Duration::from_secs(10));
-
-        thread::sleep(Duration::from_secs(3));
-
-        let logged_in_refresh = logged_in.clone();
-        let client_refresh = client.clone();
+    verbose!("等待登录完成...");
+    loop {
+        if logged_in.load(Ordering::Relaxed) {
+            verbose!("登录完成，继续运行");
+            return;
+        }
+        if timed_out.load(Ordering::Relaxed)
+            || ctrl_c_count.load(Ordering::SeqCst) > 0
+            || logger::strict_failed()
+        {
+            return;
+        }
+        let exited = match child.lock() {
+            Ok(mut guard) => match guard.as_mut().map(|c| c.try_wait()) {
+                Some(Ok(Some(status))) => Some(status.code()),
+                _ => None,
+            },
+            Err(_) => None,
+        };
+        if let Some(code) = exited {
+            eprintln!("pmhq 在登录完成前退出，状态码: {:?}", code);
+            wait_exit(code.filter(|&c| c != 0).unwrap_or(1));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// 从 PMHQ 宣布监听地址的输出行中取出端口，例如 `listening on http://127.0.0.1:13001`、
+/// `listening on port 13001` 或 `HTTP 服务监听端口: 13001`
+fn parse_bound_port(line: &str) -> Option<u16> {
+    let lower = line.to_ascii_lowercase();
+    if !lower.contains("listen") && !line.contains("监听") {
+        return None;
+    }
+    let leading_port = |s: &str| {
+        let digits: String = s
+            .trim_start_matches([' ', ':', '：', '='])
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        digits.parse::<u16>().ok().filter(|&p| p > 0)
+    };
+    ["port", "端口"]
+        .iter()
+        .filter_map(|key| lower.find(key).map(|pos| &lower[pos + key.len()..]))
+        .find_map(leading_port)
+        .or_else(|| lower.rmatch_indices(':').find_map(|(pos, _)| leading_port(&lower[pos..])))
+}
+
+/// `--verbose` 下输出完整的 pmhq 命令行、工作目录和额外的环境变量，便于手动复现
+fn log_command(cmd: &Command) {
+    let quote = |s: &std::ffi::OsStr| {
+        let s = s.to_string_lossy();
+        if s.is_empty() || s.contains(char::is_whitespace) || s.contains('"') {
+            format!("\"{}\"", s.replace('"', "\\\""))
+        } else {
+            s.into_owned()
+        }
+    };
+    let line: Vec<String> = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(quote)
+        .collect();
+    verbose!("pmhq 命令行: {}", line.join(" "));
+
+    let cwd = cmd
+        .get_current_dir()
+        .map(Path::to_path_buf)
+        .or_else(|| env::current_dir().ok())
+        .unwrap_or_default();
+    verbose!("工作目录: {}", cwd.display());
+    for (key, value) in cmd.get_envs() {
+        match value {
+            Some(value) => verbose!(
+                "环境变量: {}={}",
+                key.to_string_lossy(),
+                http::redact_url(&value.to_string_lossy())
+            ),
+            None => verbose!("环境变量: 移除 {}", key.to_string_lossy()),
+        }
+    }
+}
+
+/// 把子进程的 stdout / stderr 接到输出通道
+fn attach_output(child: &mut GroupChild, line_tx: &mpsc::SyncSender<(ChildStream, String)>) {
+    if let Some(stdout) = child.inner().stdout.take() {
+        spawn_line_reader(stdout, ChildStream::Stdout, line_tx.clone());
+    }
+    if let Some(stderr) = child.inner().stderr.take() {
+        spawn_line_reader(stderr, ChildStream::Stderr, line_tx.clone());
+    }
+}
+
+/// `--restart-on-crash`：统计窗口内的异常退出次数，计算重启前的等待时间
+struct RestartPolicy {
+    max_restarts: u32,
+    crashes: Vec<Instant>,
+}
+
+impl RestartPolicy {
+    fn new(max_restarts: u32) -> Self {
+        Self { max_restarts, crashes: Vec::new() }
+    }
+
+    /// 记录一次异常退出，返回重启前的等待时间；窗口内次数超过上限时返回 None
+    fn on_crash(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        self.crashes.retain(|t| now.duration_since(*t) < RESTART_WINDOW);
+        self.crashes.push(now);
+        let count = self.crashes.len() as u32;
+        if count > self.max_restarts {
+            return None;
+        }
+        // 1、2、4、8 … 秒，最长 RESTART_BACKOFF_MAX
+        let delay = RESTART_BACKOFF_INITIAL * (1 << (count - 1).min(6));
+        Some(delay.min(RESTART_BACKOFF_MAX))
+    }
+
+    fn reset(&mut self) {
+        self.crashes.clear();
+    }
+}
+
+/// 请求子进程组正常退出：Unix 发送 SIGTERM，Windows 使用不带 /F 的 taskkill 发送关闭消息
+fn terminate_gracefully(child: &mut GroupChild) {
+    #[cfg(not(target_os = "windows"))]
+    {
+        use command_group::{Signal, UnixChildExt};
+        if let Err(e) = child.signal(Signal::SIGTERM) {
+            verbose!("发送 SIGTERM 失败: {}", e);
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let _ = Command::new("taskkill")
+            .args(["/T", "/PID", &child.id().to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ChildStream {
+    Stdout,
+    Stderr,
+}
+
+impl ChildStream {
+    fn name(self) -> &'static str {
+        match self {
+            ChildStream::Stdout => "stdout",
+            ChildStream::Stderr => "stderr",
+        }
+    }
+}
+
+fn spawn_line_reader<R: std::io::Read + Send + 'static>(
+    source: R,
+    stream: ChildStream,
+    tx: mpsc::SyncSender<(ChildStream, String)>,
+) {
+    thread::spawn(move || {
+        use std::io::{BufRead, BufReader};
+        // 按字节读取，GBK 等非 UTF-8 输出替换为 U+FFFD 而不是让读取中断
+        let mut reader = BufReader::new(source);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.trim_end_matches(['\n', '\r']).to_string();
+            if tx.send((stream, line)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// 转发子进程的一行输出，`--json-logs` 下包装为 NDJSON
+fn forward_line(out: &mut impl Write, stream: ChildStream, line: &str, json: bool) {
+    // --daemon 的后台进程中 stdout 就是日志文件，只写入一次
+    if !logger::is_detached() {
+        if json {
+            let ts = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0);
+            let record = serde_json::json!({ "stream": stream.name(), "ts": ts, "line": line });
+            let _ = writeln!(out, "{}", record);
+        } else {
+            let _ = writeln!(out, "{}", line);
+        }
+        let _ = out.flush();
+    }
+    logger::log_line(logger::Level::Info, &format!("[pmhq {}] {}", stream.name(), line));
+}
+
+/// `--list-accounts`：临时启动 PMHQ（不启动 LLBot），列出本机登录过的账号后退出
+fn list_accounts(
+    pmhq_exe: &Path,
+    args: &[String],
+    bind: Option<IpAddr>,
+    request_timeout: Duration,
+) -> ! {
+    let bind_addr = bind.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    let port = find_available_port(bind_addr, DEFAULT_PORT, PORT_RANGE_END).unwrap_or_else(|| {
+        eprintln!("错误: 无法找到可用端口 ({}-{})", DEFAULT_PORT, PORT_RANGE_END);
+        std::process::exit(1);
+    });
+
+    let mut cmd = Command::new(pmhq_exe);
+    cmd.arg("--port").arg(port.to_string());
+    if let Some(addr) = bind {
+        cmd.arg("--host").arg(addr.to_string());
+    }
+    let mut child = match cmd
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .group_spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("启动 pmhq 失败: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let client = PMHQClient::new(port).with_timeout(request_timeout);
+    let deadline = Instant::now() + Duration::from_secs(60);
+    while !client.ping() {
+        let exited = matches!(child.try_wait(), Ok(Some(_)) | Err(_));
+        if exited || Instant::now() >= deadline {
+            let _ = child.kill();
+            eprintln!("错误: PMHQ 未能在 60 秒内就绪");
+            std::process::exit(1);
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    let result = client.get_login_list();
+    let _ = child.kill();
+
+    match result {
+        Ok(Some(accounts)) if accounts.is_empty() => println!("本机没有登录过的账号"),
+        Ok(Some(accounts)) => {
+            for account in accounts {
+                let mut line = account.uin;
+                if !account.nickname.is_empty() {
+                    line.push_str(&format!("\t{}", account.nickname));
+                }
+                if let Some(time) = account.last_login {
+                    line.push_str(&format!("\t{}", time));
+                }
+                println!("{}", line);
+            }
+        }
+        Ok(None) => {
+            eprintln!("当前 pmhq 版本不支持获取已登录账号列表");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("获取账号列表失败: {}", e);
+            std::process::exit(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+/// 检查可执行文件是否在运行期间被删除（常见于杀毒软件隔离），并给出处理建议
+fn report_removed_binaries(binaries: &[&Path]) {
+    let missing: Vec<&&Path> = binaries.iter().filter(|p| !p.exists()).collect();
+    if missing.is_empty() {
+        return;
+    }
+
+    eprintln!();
+    eprintln!("以下文件已不存在，可能被杀毒软件隔离或删除:");
+    for path in &missing {
+        eprintln!("  - {}", path.display());
+    }
+    eprintln!("请将 LLBot 所在目录添加到杀毒软件的排除列表，");
+    eprintln!("然后运行 `llbot --reinstall` 重新下载缺失的组件");
+}
+
+#[derive(Clone)]
+struct LoginOptions {
+    qrcode_path: PathBuf,
+    show_terminal_qr: bool,
+    /// 为 false 时（`--qr-stdout-only`）不写入 qrcode.png / qrcode.svg
+    save_image: bool,
+    qr_style: QrStyle,
+    /// 设置后同时生成 qrcode.svg
+    qr_svg: Option<SvgOptions>,
+    /// 同时把二维码以方块字符写入日志文件
+    log_qr: bool,
+    /// `--export-qr-ascii`：同时把二维码以纯 ASCII 写入该文本文件
+    qr_ascii_path: Option<PathBuf>,
+    /// 登录事件后等待账号信息就绪的最长时间，0 表示不等待
+    settle_delay: Duration,
+    /// `--account` 指定的 QQ 号，登录后校验是否一致
+    account: Option<String>,
+    /// `--on-login-webhook`：登录成功后 POST 账号信息的地址
+    webhook: Option<String>,
+    /// `--request-timeout`：PMHQ 请求的超时，SSE 连接的超时按倍数放大
+    request_timeout: Duration,
+    /// 整个登录阶段的最长时间，包括所有重连
+    timeout: Option<Duration>,
+    /// 登录超时后置位，由主循环负责结束子进程
+    timed_out: Arc<AtomicBool>,
+    /// 子进程被重启后置位，旧的登录流程不再输出登录结果
+    cancelled: Arc<AtomicBool>,
+}
+
+fn login_client(port: Arc<AtomicU16>, request_timeout: Duration) -> PMHQClient {
+    PMHQClient::with_shared_port(port)
+        .with_timeout(request_timeout)
+        .with_sse_timeout(request_timeout * SSE_TIMEOUT_FACTOR)
+}
+
+/// 有登录令牌时先尝试令牌登录，失败或未提供令牌时回退到扫码登录
+fn start_login(
+    port: &Arc<AtomicU16>,
+    token: Option<String>,
+    logged_in: Arc<AtomicBool>,
+    opts: LoginOptions,
+) {
+    let Some(token) = token else {
+        start_login_listener(port.clone(), logged_in, opts);
+        return;
+    };
+
+    let port = port.clone();
+    thread::spawn(move || {
+        let client = login_client(port.clone(), opts.request_timeout);
+        wait_for_pmhq(&client, Instant::now() + PMHQ_READY_TIMEOUT);
+        // 等待期间子进程已被重启时，由新的登录流程负责
+        if opts.cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+
+        println!("正在使用登录令牌登录...");
+        let result = client.login_with_token(&token);
+        if opts.cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        match result {
+            Ok(()) => {
+                logged_in.store(true, Ordering::Relaxed);
+                print_login_summary(
+                    &client,
+                    opts.settle_delay,
+                    opts.account.as_deref(),
+                    opts.webhook.as_deref(),
+                );
+            }
+            Err(e) => {
+                eprintln!("令牌登录失败: {}，改用扫码登录", e);
+                start_login_listener(port, logged_in, opts);
+            }
+        }
+    });
+}
+
+fn start_login_listener(port: Arc<AtomicU16>, logged_in: Arc<AtomicBool>, opts: LoginOptions) {
+    let LoginOptions {
+        qrcode_path,
+        mut show_terminal_qr,
+        mut save_image,
+        qr_style,
+        qr_svg,
+        log_qr,
+        qr_ascii_path,
+        settle_delay,
+        account,
+        webhook,
+        request_timeout,
+        timeout,
+        timed_out,
+        cancelled,
+    } = opts;
+
+    thread::spawn(move || {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let past_deadline = move || deadline.is_some_and(|d| Instant::now() >= d);
+        let client = login_client(port, request_timeout);
+
+        // 等 PMHQ 的 API 可用后再请求二维码，慢速机器上固定等待不够
+        let ready_deadline = Instant::now() + PMHQ_READY_TIMEOUT;
+        let ready_deadline = deadline.map_or(ready_deadline, |d| d.min(ready_deadline));
+        if !wait_for_pmhq(&client, ready_deadline) {
+            warning!("PMHQ 在 {} 秒内未响应，仍继续等待登录", PMHQ_READY_TIMEOUT.as_secs());
+        }
+
+        // QQ 沿用了之前的登录会话时不再请求和显示二维码
+        if let Ok(info) = client.get_self_info() {
+            verbose!("QQ {} 已处于登录状态，跳过扫码", info.uin);
+            logged_in.store(true, Ordering::Relaxed);
+            if !cancelled.load(Ordering::Relaxed) {
+                print_login_summary(&client, settle_delay, account.as_deref(), webhook.as_deref());
+            }
+            return;
+        }
+
+        let logged_in_refresh = logged_in.clone();
+        let cancelled_refresh = cancelled.clone();
+        let stop_refresh = move || {
+            logged_in_refresh.load(Ordering::Relaxed)
+                || cancelled_refresh.load(Ordering::Relaxed)
+                || past_deadline()
+        };
+        let client_refresh = client.clone();
         thread::spawn(move || {
+            let mut failures = 0;
             loop {
-                if logged_in_refresh.load(Ordering::Relaxed) {
+                if stop_refresh() {
                     break;
                 }
-                let _ = client_refresh.request_qrcode();
-                for _ in 0..120 {
-                    if logged_in_refresh.load(Ordering::Relaxed) {
+                let wait = match client_refresh.request_qrcode() {
+                    Ok(()) => {
+                        failures = 0;
+                        QR_REFRESH_INTERVAL
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        verbose!("{}（连续第 {} 次）", e, failures);
+                        if failures == QR_FAILURE_THRESHOLD {
+                            eprintln!("多次请求二维码失败，请检查 pmhq 是否正常。最后一次错误: {}", e);
+                        }
+                        QR_RETRY_INTERVAL
+                    }
+                };
+                for _ in 0..wait {
+                    if stop_refresh() {
+                        break;
+                    }
+                    if QR_REFRESH_REQUESTED.swap(false, Ordering::Relaxed) {
+                        println!("收到 SIGHUP，正在刷新二维码...");
                         break;
                     }
                     thread::sleep(Duration::from_secs(1));
@@ -338,110 +1555,685 @@ fn start_login_listener(
             }
         });
 
-        client.start_sse_listener(logged_in.clone(), move |qrcode_url, png_base64| {
+        let on_qrcode = move |qrcode_url: &str, png_base64: &str| {
             if show_terminal_qr {
-                print_qrcode_terminal(qrcode_url);
+                print_qrcode_terminal(qrcode_url, qr_style);
+            }
+            if log_qr {
+                log_qrcode(qrcode_url);
+            }
+            if let Some(ref path) = qr_ascii_path {
+                match save_qrcode_ascii(qrcode_url, path) {
+                    Ok(()) => notice!("二维码文本文件: {}", path.display()),
+                    Err(e) => eprintln!("保存 ASCII 二维码失败: {}", e),
+                }
             }
 
-            if !png_base64.is_empty() {
-                if let Err(e) = save_qrcode_image(png_base64, &qrcode_path) {
-                    eprintln!("保存二维码失败: {}", e);
+            if save_image {
+                let saved = if png_base64.is_empty() {
+                    Err("PMHQ 未提供二维码图片".to_string())
                 } else {
-                    println!("二维码文件: {}", qrcode_path.display());
+                    save_qrcode_image(png_base64, &qrcode_path)
+                };
+                let saved = match saved {
+                    Ok(()) => {
+                        verbose!("二维码图片来自 PMHQ");
+                        notice!("二维码文件: {}", qrcode_path.display());
+                        Ok(())
+                    }
+                    Err(e) => {
+                        // PMHQ 的图片数据不可用时，根据二维码网址在本地生成图片
+                        verbose!("{}，改为本地生成二维码图片", e);
+                        render_qrcode_image(qrcode_url, &qrcode_path).map(|()| {
+                            notice!("二维码文件: {} (本地生成)", qrcode_path.display())
+                        })
+                    }
+                };
+                if let Err(e) = saved {
+                    // 只读或临时文件系统上写不了文件，之后刷新二维码时不再尝试
+                    warning!("无法保存二维码图片，之后只在终端显示: {}", e);
+                    save_image = false;
+                    if !show_terminal_qr && !events::enabled() {
+                        show_terminal_qr = true;
+                        print_qrcode_terminal(qrcode_url, qr_style);
+                    }
                 }
             }
 
-            println!(
+            if let Some(svg_opts) = qr_svg.filter(|_| save_image) {
+                let svg_path = qrcode_path.with_extension("svg");
+                match save_qrcode_svg(qrcode_url, &svg_path, svg_opts) {
+                    Ok(()) => notice!("二维码 SVG 文件: {}", svg_path.display()),
+                    Err(e) => eprintln!("保存 SVG 二维码失败: {}", e),
+                }
+            }
+
+            events::emit(
+                "qrcode",
+                serde_json::json!({
+                    "url": qrcode_url,
+                    "image": (save_image && qrcode_path.exists()).then(|| qrcode_path.display().to_string()),
+                }),
+            );
+            // 固定前缀的原始内容，供脚本提取或自行生成二维码
+            notice!("QRCODE_URL={}", qrcode_url);
+            notice!(
                 "二维码网址: https://api.2dcode.biz/v1/create-qr-code?data={}",
                 qrcode_url
             );
-            println!("请使用手机QQ扫码登录");
+            notice!("请使用手机QQ扫码登录");
             println!();
-        });
+            systemd::status("等待扫码登录");
+        };
 
-        if logged_in.load(Ordering::Relaxed) {
-            println!();
-            println!("================");
-            println!("登录成功!");
+        let on_state = |state: &LoginState| {
+            if let LoginState::LoggedInElsewhere(reason) = state {
+                eprintln!();
+                eprintln!("{}", color::yellow("该账号已在其他设备登录"));
+                if !reason.is_empty() {
+                    eprintln!("QQ 返回: {}", reason);
+                }
+                eprintln!("请先在其他设备上退出该账号，二维码刷新后重新扫码");
+                eprintln!();
+            }
+        };
+
+        let result = client.start_sse_listener(
+            logged_in.clone(),
+            cancelled.clone(),
+            deadline,
+            on_qrcode,
+            on_state,
+        );
+
+        if let Err(state) = result {
+            // 子进程已被重启时由新的登录流程负责，不再判定超时
+            if cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            if past_deadline() {
+                eprintln!(
+                    "错误: 登录超时（{} 秒），最后状态: {}",
+                    timeout.unwrap_or_default().as_secs(),
+                    state
+                );
+            } else {
+                eprintln!("错误: 长时间无法连接 PMHQ 事件流，已停止等待登录，最后状态: {}", state);
+            }
+            timed_out.store(true, Ordering::Relaxed);
+            return;
+        }
+
+        if logged_in.load(Ordering::Relaxed) && !cancelled.load(Ordering::Relaxed) {
+            print_login_summary(&client, settle_delay, account.as_deref(), webhook.as_deref());
+        }
+    });
+}
+
+fn print_login_summary(
+    client: &PMHQClient,
+    settle_delay: Duration,
+    account: Option<&str>,
+    webhook: Option<&str>,
+) {
+    let info = wait_for_self_info(client, settle_delay);
+    let (uin, nickname) = match info {
+        Ok(ref info) => (Some(info.uin.as_str()), Some(info.nickname.as_str())),
+        Err(_) => (None, None),
+    };
+    events::emit("login", serde_json::json!({ "uin": uin, "nickname": nickname }));
+    systemd::ready(&match uin {
+        Some(uin) => format!("已登录 QQ {}", uin),
+        None => "已登录".to_string(),
+    });
+
+    notice!();
+    notice!("================");
+    notice!("{}", color::green("登录成功!"));
+
+    if let Ok(ref info) = info {
+        notice!("QQ号: {}", info.uin);
+        if !info.nickname.is_empty() {
+            notice!("昵称: {}", info.nickname);
+        }
+    }
+    notice!("================");
+    notice!();
+
+    if let (Some(expected), Ok(info)) = (account, &info) {
+        if info.uin != expected {
+            warning!("登录的账号 {} 与 --account 指定的 {} 不一致", info.uin, expected);
+        }
+    }
+
+    if let Some(url) = webhook {
+        notify_login_webhook(url, uin, nickname);
+    }
+}
+
+/// 向 `--on-login-webhook` 地址 POST 登录事件；失败只记录，不影响运行
+fn notify_login_webhook(url: &str, uin: Option<&str>, nickname: Option<&str>) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let body = serde_json::json!({
+        "event": "login",
+        "uin": uin,
+        "nickname": nickname,
+        "timestamp": timestamp,
+    });
+    let req = http::post(url)
+        .timeout(LOGIN_WEBHOOK_TIMEOUT)
+        .set("Content-Type", "application/json");
+    match http::send_string(req, &body.to_string()) {
+        Ok(_) => verbose!("已通知登录 webhook: {}", http::redact_url(url)),
+        Err(e) => warning!("登录 webhook {} 请求失败: {}", http::redact_url(url), e),
+    }
+}
+
+struct WatchdogOptions {
+    interval: Duration,
+    /// 连续探测失败多少次后判定 PMHQ 无响应
+    max_failures: u32,
+    request_timeout: Duration,
+}
+
+/// 定期探测 PMHQ API，进程仍在但连续多次无响应时结束整个进程组，
+/// 之后由主循环按 `--restart-on-crash` 重启，未启用时启动器以非零状态码退出
+fn start_watchdog(
+    port: &Arc<AtomicU16>,
+    child: Arc<Mutex<Option<GroupChild>>>,
+    opts: WatchdogOptions,
+) {
+    let client = PMHQClient::with_shared_port(port.clone())
+        .with_timeout(opts.interval.min(opts.request_timeout));
+    thread::spawn(move || {
+        let mut seen_alive = false;
+        let mut failures = 0;
+        loop {
+            thread::sleep(opts.interval);
+            if client.ping() {
+                seen_alive = true;
+                failures = 0;
+                continue;
+            }
 
-            if let Ok(info) = client.get_self_info() {
-                println!("QQ号: {}", info.uin);
-                if !info.nickname.is_empty() {
-                    println!("昵称: {}", info.nickname);
+            // PMHQ 启动完成前的失败不计数
+            if !seen_alive {
+                continue;
+            }
+            failures += 1;
+            verbose!("PMHQ 探测失败 ({}/{})", failures, opts.max_failures);
+            if failures < opts.max_failures {
+                continue;
+            }
+            eprintln!(
+                "{}",
+                color::yellow(&format!(
+                    "警告: PMHQ 进程仍在运行，但已连续 {} 次无响应，可能已卡死，正在结束",
+                    failures
+                ))
+            );
+            if let Ok(mut guard) = child.lock() {
+                if let Some(ref mut c) = *guard {
+                    let _ = c.kill();
                 }
             }
-            println!("================");
-            println!();
+            // 重启后的新进程同样要先响应一次才开始计数
+            seen_alive = false;
+            failures = 0;
         }
     });
 }
 
-fn migrate_old_files(exe_dir: &Path) {
-    // 迁移 data 目录
-    let data_dir = exe_dir.join("data");
-    let target_data_dir = exe_dir.join("bin/llbot/data");
-    if data_dir.exists() && data_dir.is_dir() {
-        println!("检测到 data 目录，正在移动到 bin/llbot/...");
-        if target_data_dir.exists() {
-            let _ = fs::remove_dir_all(&target_data_dir);
+/// 轮询直到 PMHQ 的 API 响应或到达截止时间，返回是否已就绪
+fn wait_for_pmhq(client: &PMHQClient, deadline: Instant) -> bool {
+    systemd::status("等待 PMHQ 响应");
+    loop {
+        if client.ping() {
+            verbose!("PMHQ 已就绪");
+            return true;
         }
-        if fs::rename(&data_dir, &target_data_dir).is_err() {
-            if let Err(e) = copy_dir_recursive(&data_dir, &target_data_dir) {
-                eprintln!("警告: 移动 data 目录失败: {}", e);
-            } else {
-                let _ = fs::remove_dir_all(&data_dir);
-                println!("data 目录移动完成");
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+/// 轮询 get_self_info 直到账号信息可用，至少重试 `SELF_INFO_RETRIES` 次且不早于 settle 时间放弃
+fn wait_for_self_info(client: &PMHQClient, settle: Duration) -> Result<SelfInfo, String> {
+    let deadline = Instant::now() + settle;
+    let mut retries = 0;
+    loop {
+        let result = client.get_self_info();
+        if result.is_ok() || (retries >= SELF_INFO_RETRIES && Instant::now() >= deadline) {
+            return result;
+        }
+        retries += 1;
+        verbose!("账号信息尚未就绪，继续等待");
+        thread::sleep(SELF_INFO_RETRY_DELAY);
+    }
+}
+
+/// 默认的数据目录 `bin/llbot/data`；指定 `account` 时为 `bin/llbot/data/<uin>`，各账号的数据互不覆盖
+fn default_data_dir(exe_dir: &Path, account: Option<&str>) -> PathBuf {
+    match account {
+        Some(uin) => exe_dir.join("bin/llbot/data").join(uin),
+        None => exe_dir.join("bin/llbot/data"),
+    }
+}
+
+/// 迁移旧版本放在程序目录下的文件，data 目录迁移到 `data_dir`；`--data-dir` 指向
+/// `bin/llbot/data` 之外时不迁移 data。`dry_run` 时只列出将要进行的移动，不改动任何文件
+fn migrate_old_files(exe_dir: &Path, data_dir: &Path, dry_run: bool) {
+    let marker = exe_dir.join(MIGRATION_MARKER);
+    if marker.exists() {
+        if dry_run {
+            println!("已完成过迁移，启动时不会再迁移；删除 {} 后会重新检查", marker.display());
+        }
+        return;
+    }
+
+    let mut moves = vec![(
+        exe_dir.join("pmhq_config.json"),
+        exe_dir.join("bin/pmhq/pmhq_config.json"),
+        false,
+    )];
+    // 跳过了旧版 data 时不写入标记，不再指定 --data-dir 后仍会迁移
+    let mut skipped_data = false;
+    if data_dir.starts_with(exe_dir.join("bin/llbot/data")) {
+        moves.insert(0, (exe_dir.join("data"), data_dir.to_path_buf(), true));
+    } else {
+        verbose!("数据目录 {} 不在默认位置，不迁移旧版 data 目录", data_dir.display());
+        skipped_data = exe_dir.join("data").is_dir();
+    }
+
+    let mut planned = 0;
+    let mut failed = false;
+    for (src, dst, is_dir) in &moves {
+        let present = if *is_dir { src.is_dir() } else { src.is_file() };
+        if !present {
+            continue;
+        }
+        planned += 1;
+
+        if dry_run {
+            let overwrite = if dst.exists() { "（将覆盖已存在的目标）" } else { "" };
+            println!("将移动 {} -> {}{}", src.display(), dst.display(), overwrite);
+            continue;
+        }
+
+        println!("检测到 {}，正在移动到 {}...", src.display(), dst.display());
+        match move_path(src, dst, *is_dir) {
+            Ok(()) => println!("已移动 {} -> {}", src.display(), dst.display()),
+            Err(e) => {
+                failed = true;
+                warning!("移动 {} 失败: {}", src.display(), e);
             }
+        }
+    }
+
+    if dry_run {
+        if planned == 0 {
+            println!("没有需要迁移的文件");
+        }
+    } else if !failed && !skipped_data {
+        // 全部完成后不再检查，失败时下次启动重试
+        if let Err(e) = fs::write(&marker, b"") {
+            verbose!("写入迁移标记 {} 失败: {}", marker.display(), e);
+        }
+    }
+}
+
+/// 移动文件或目录并覆盖目标，跨文件系统时改为复制后删除
+fn move_path(src: &Path, dst: &Path, is_dir: bool) -> std::io::Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if dst.exists() {
+        if is_dir {
+            fs::remove_dir_all(dst)?;
         } else {
-            println!("data 目录移动完成");
+            fs::remove_file(dst)?;
         }
     }
+    if fs::rename(src, dst).is_ok() {
+        return Ok(());
+    }
+    fsutil::copy_recursive(src, dst)?;
+    if is_dir {
+        fs::remove_dir_all(src)
+    } else {
+        fs::remove_file(src)
+    }
+}
 
-    // 迁移 pmhq_config.json
-    let pmhq_config = exe_dir.join("pmhq_config.json");
-    let target_pmhq_config = exe_dir.join("bin/pmhq/pmhq_config.json");
-    if pmhq_config.exists() && pmhq_config.is_file() {
-        println!("检测到 pmhq_config.json，正在移动到 bin/pmhq/...");
-        if target_pmhq_config.exists() {
-            let _ = fs::remove_file(&target_pmhq_config);
+/// 移除并返回 `--key=value` 形式参数的值，重复出现时取最后一个
+fn take_value(args: &mut Vec<String>, prefix: &str) -> Option<String> {
+    let mut value = None;
+    args.retain(|a| match a.strip_prefix(prefix) {
+        Some(v) => {
+            value = Some(v.to_string());
+            false
         }
-        if fs::rename(&pmhq_config, &target_pmhq_config).is_err() {
-            if let Err(e) = fs::copy(&pmhq_config, &target_pmhq_config) {
-                eprintln!("警告: 移动 pmhq_config.json 失败: {}", e);
-            } else {
-                let _ = fs::remove_file(&pmhq_config);
-                println!("pmhq_config.json 移动完成");
+        None => true,
+    });
+    value
+}
+
+/// 移除并按顺序返回可重复参数 `--key=value` 的所有值
+fn take_values(args: &mut Vec<String>, prefix: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    args.retain(|a| match a.strip_prefix(prefix) {
+        Some(v) => {
+            values.push(v.to_string());
+            false
+        }
+        None => true,
+    });
+    values
+}
+
+/// 移除并解析 `--env KEY=VALUE` / `--env=KEY=VALUE`，可重复指定
+fn take_env_args(args: &mut Vec<String>) -> Vec<(String, String)> {
+    let mut specs = take_values(args, "--env=");
+    while let Some(i) = args.iter().position(|a| a == "--env") {
+        args.remove(i);
+        if i >= args.len() {
+            eprintln!("错误: --env 后需要 KEY=VALUE");
+            wait_exit(1);
+        }
+        specs.push(args.remove(i));
+    }
+    specs
+        .into_iter()
+        .map(|spec| match spec.split_once('=') {
+            Some((key, value)) if !key.is_empty() => (key.to_string(), value.to_string()),
+            _ => {
+                eprintln!("错误: --env 格式应为 KEY=VALUE: {}", spec);
+                wait_exit(1);
+            }
+        })
+        .collect()
+}
+
+fn parse_secs(name: &str, value: &str) -> Duration {
+    match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => {
+            eprintln!("错误: {} 需要一个非负整数秒数: {}", name, value);
+            wait_exit(1);
+        }
+    }
+}
+
+fn parse_count(name: &str, value: &str) -> u32 {
+    match value.parse::<u32>() {
+        Ok(n) if n > 0 => n,
+        _ => {
+            eprintln!("错误: {} 需要一个正整数: {}", name, value);
+            wait_exit(1);
+        }
+    }
+}
+
+/// 移除并返回布尔参数是否出现
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|a| a != flag);
+    args.len() != before
+}
+
+/// 解析 `START-END`，要求 START < END
+fn parse_port_range(value: &str) -> Option<(u16, u16)> {
+    let (start, end) = value.split_once('-')?;
+    let start = start.trim().parse::<u16>().ok()?;
+    let end = end.trim().parse::<u16>().ok()?;
+    (start > 0 && start < end).then_some((start, end))
+}
+
+/// 在 `bind` 地址上依次尝试绑定，返回第一个可用的端口
+fn find_available_port(bind: IpAddr, start: u16, end: u16) -> Option<u16> {
+    (start..end).find(|&port| TcpListener::bind((bind, port)).is_ok())
+}
+
+/// 绑定 0 端口，由系统从临时端口范围中分配一个空闲端口
+fn os_assigned_port(bind: IpAddr) -> Option<u16> {
+    let listener = TcpListener::bind((bind, 0)).ok()?;
+    listener.local_addr().ok().map(|addr| addr.port())
+}
+
+/// 结束所有正在运行的 LLBot 相关进程后退出
+fn stop_running_processes() -> ! {
+    let running = updater::check_running_processes();
+    if running.is_empty() {
+        println!("没有正在运行的 LLBot 相关进程");
+        wait_exit(0);
+    }
+
+    let mut failed = false;
+    for (name, pid) in &running {
+        if updater::kill_process(*pid) {
+            println!("已结束 {} (PID: {})", name, pid);
+        } else {
+            eprintln!("结束 {} (PID: {}) 失败", name, pid);
+            failed = true;
+        }
+    }
+    wait_exit(if failed { 1 } else { 0 });
+}
+
+/// SIGHUP 只置位标志，由二维码刷新线程处理；登录后该线程已退出，信号不再有任何效果
+#[cfg(not(target_os = "windows"))]
+fn install_qr_refresh_signal() {
+    extern "C" fn on_sighup(_: libc::c_int) {
+        QR_REFRESH_REQUESTED.store(true, Ordering::Relaxed);
+    }
+    // SAFETY: 处理函数只写入一个原子变量，是异步信号安全的
+    unsafe {
+        libc::signal(libc::SIGHUP, on_sighup as *const () as libc::sighandler_t);
+    }
+}
+
+/// `--daemon`：去掉该参数后在新会话中重新启动自身，输出只写入日志文件，
+/// 写入 PID 文件后立即返回 shell
+#[cfg(not(target_os = "windows"))]
+fn daemonize(exe_dir: &Path, account: Option<&str>, qrcode_path: &Path) -> ! {
+    use std::os::unix::process::CommandExt;
+
+    let exe = env::current_exe().unwrap_or_else(|e| {
+        eprintln!("错误: 无法获取程序路径: {}", e);
+        std::process::exit(1);
+    });
+    // 日志宏之外的输出（如 panic 信息）也写入日志文件，便于排查后台进程异常退出
+    let log_path = logger::file_path(exe_dir);
+    let log = log_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::OpenOptions::new().create(true).append(true).open(&log_path))
+        .and_then(|file| Ok((file.try_clone()?, file)))
+        .unwrap_or_else(|e| {
+            eprintln!("错误: 无法打开日志文件 {}: {}", log_path.display(), e);
+            std::process::exit(1);
+        });
+    let mut cmd = Command::new(exe);
+    cmd.args(env::args().skip(1).filter(|a| a != "--daemon"))
+        .env(DAEMON_ENV, "1")
+        .stdin(Stdio::null())
+        .stdout(log.0)
+        .stderr(log.1);
+    // 脱离控制终端，关闭终端后不会收到 SIGHUP
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
             }
+            Ok(())
+        });
+    }
+
+    let child = cmd.spawn().unwrap_or_else(|e| {
+        eprintln!("错误: 后台启动失败: {}", e);
+        std::process::exit(1);
+    });
+    let pid_path = pidfile::path(exe_dir, account);
+    if let Err(e) = fs::write(&pid_path, format!("{}\n", child.id())) {
+        warning!("写入 PID 文件 {} 失败: {}", pid_path.display(), e);
+    }
+    println!("已转入后台运行 (PID: {})", child.id());
+    println!("日志文件: {}", logger::file_path(exe_dir).display());
+    notice!("二维码文件: {}", qrcode_path.display());
+    std::process::exit(0);
+}
+
+/// `--kill-qq`：结束残留的 QQ 进程并等待其退出，避免 PMHQ 无法接管会话
+fn kill_running_qq() {
+    let is_qq = |name: &str| name.trim_end_matches(".exe").eq_ignore_ascii_case("qq");
+    let running: Vec<(String, u32)> = updater::check_running_processes()
+        .into_iter()
+        .filter(|(name, _)| is_qq(name))
+        .collect();
+    if running.is_empty() {
+        verbose!("没有正在运行的 QQ");
+        return;
+    }
+
+    for (name, pid) in &running {
+        if updater::kill_process(*pid) {
+            println!("已结束 {} (PID: {})", name, pid);
         } else {
-            println!("pmhq_config.json 移动完成");
+            warning!("结束 {} (PID: {}) 失败", name, pid);
         }
     }
+
+    let deadline = Instant::now() + QQ_EXIT_TIMEOUT;
+    loop {
+        let remaining = updater::check_running_processes()
+            .into_iter()
+            .any(|(name, pid)| is_qq(&name) && running.iter().any(|(_, p)| *p == pid));
+        if !remaining {
+            return;
+        }
+        if Instant::now() >= deadline {
+            warning!("QQ 未在 {} 秒内退出", QQ_EXIT_TIMEOUT.as_secs());
+            return;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// 运行 `node --version`，主版本低于 llbot.js 要求时发出警告
+fn check_node_version(node_path: &Path, llbot_dir: &Path) {
+    let output = match Command::new(node_path).arg("--version").output() {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            warning!(
+                "node --version 执行失败 ({})，node 可能已损坏或与系统不兼容",
+                output.status
+            );
+            return;
+        }
+        Err(e) => {
+            warning!("无法运行 {}: {}", node_path.display(), e);
+            return;
+        }
+    };
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let Some(major) = first_number(version.trim_start_matches('v')) else {
+        verbose!("无法解析 node 版本: {}", version);
+        return;
+    };
+
+    let engines = fs::read_to_string(llbot_dir.join("package.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .and_then(|json| json.pointer("/engines/node")?.as_str().map(String::from));
+    // 形如 ">=18"、"^20.0.0"、"18 || >=20"，取各备选范围中最小的主版本
+    let required = engines
+        .as_deref()
+        .and_then(|range| range.split("||").filter_map(first_number).min())
+        .unwrap_or(MIN_NODE_MAJOR);
+    verbose!(
+        "node 版本: {}，llbot.js 要求: {}",
+        version,
+        engines.as_deref().unwrap_or("未声明")
+    );
+    if major < required {
+        warning!(
+            "node 版本 {} 低于 llbot.js 要求的 {}，可能无法正常运行，请运行 `llbot --reinstall`",
+            version,
+            required
+        );
+    }
 }
 
-fn find_available_port(start: u16, end: u16) -> Option<u16> {
-    for port in start..end {
-        if TcpListener::bind(("127.0.0.1", port)).is_ok() {
-            return Some(port);
+/// `path` 是否为可执行的文件
+fn check_executable(path: &Path) -> Result<(), String> {
+    let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+    if !metadata.is_file() {
+        return Err("不是文件".to_string());
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err("缺少执行权限".to_string());
         }
     }
-    None
+    Ok(())
+}
+
+/// 字符串中第一个连续数字
+fn first_number(s: &str) -> Option<u32> {
+    let start = s.find(|c: char| c.is_ascii_digit())?;
+    let digits: String = s[start..].chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
 }
 
+/// 等待按键后退出，避免双击启动时控制台窗口关闭、看不到错误信息；
+/// 指定 `--foreground`、stdin 不是终端（服务管理器、管道）或在已有的终端中运行时直接退出
 fn wait_exit(code: i32) -> ! {
-    println!("\n按任意键退出...");
-    let _ = std::io::stdin().read_line(&mut String::new());
+    pidfile::release();
+    events::emit("exit", serde_json::json!({ "code": code }));
+    let pause = !FOREGROUND.load(Ordering::Relaxed)
+        && std::io::stdin().is_terminal()
+        && owns_console();
+    if pause {
+        println!("\n按任意键退出...");
+        let _ = std::io::stdin().read_line(&mut String::new());
+    }
     std::process::exit(code);
 }
 
+/// 控制台窗口是否由本进程创建（双击启动）：附着在该控制台上的只有本进程
 #[cfg(target_os = "windows")]
-fn get_qq_path_from_registry() -> Option<String> {
+fn owns_console() -> bool {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetConsoleProcessList(process_ids: *mut u32, count: u32) -> u32;
+    }
+    let mut ids = [0u32; 2];
+    // SAFETY: 缓冲区长度与传入的 count 一致；返回值为附着的进程总数，0 表示失败
+    let attached = unsafe { GetConsoleProcessList(ids.as_mut_ptr(), ids.len() as u32) };
+    attached == 1
+}
+
+/// 其他平台没有双击启动时自动创建的控制台，终端中运行时保持原有的等待行为
+#[cfg(not(target_os = "windows"))]
+fn owns_console() -> bool {
+    true
+}
+
+/// 查找本机安装的 QQ：Windows 读取注册表中的卸载信息，Linux / macOS 检查默认安装位置
+#[cfg(target_os = "windows")]
+fn find_qq_path() -> Option<String> {
     use winreg::enums::*;
     use winreg::RegKey;
 
     let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-    let key = hklm
-        .open_subkey(r"SOFTWARE\WOW6432Node\Microsoft\Windows\CurrentVersion\Uninstall\QQ")
-        .ok()?;
+    let key = hklm.open_subkey(qq_version::UNINSTALL_KEY).ok()?;
 
     let uninstall_path: String = key.get_value("UninstallString").ok()?;
     let uninstall_path = uninstall_path.trim_matches('"');
@@ -457,107 +2249,102 @@ fn get_qq_path_from_registry() -> Option<String> {
 }
 
 #[cfg(not(target_os = "windows"))]
-fn get_qq_path_from_registry() -> Option<String> {
-    None
+fn find_qq_path() -> Option<String> {
+    qq_path_candidates()
+        .into_iter()
+        .find(|p| p.is_file())
+        .map(|p| p.to_string_lossy().to_string())
 }
 
-#[cfg(target_os = "windows")]
-fn download_and_install_qq() -> bool {
-    println!("正在下载 QQ...");
+#[cfg(target_os = "macos")]
+fn qq_path_candidates() -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from("/Applications/QQ.app/Contents/MacOS/QQ")];
+    if let Some(home) = env::var_os("HOME") {
+        candidates.push(Path::new(&home).join("Applications/QQ.app/Contents/MacOS/QQ"));
+    }
+    candidates
+}
 
-    let temp_dir = env::temp_dir();
-    let temp_file = temp_dir.join("QQ_Setup.exe");
+#[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+fn qq_path_candidates() -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from("/opt/QQ/qq"), PathBuf::from("/usr/bin/qq")];
+    if let Some(home) = env::var_os("HOME") {
+        let share = Path::new(&home).join(".local/share");
+        candidates.push(share.join("QQ/qq"));
+        candidates.push(share.join("flatpak/exports/bin/com.qq.QQ"));
+    }
+    candidates
+}
 
-    match ureq::get(QQ_DOWNLOAD_URL)
-        .timeout(std::time::Duration::from_secs(300))
-        .call()
-    {
-        Ok(resp) => {
-            let total_size = resp
-                .header("Content-Length")
-                .and_then(|s| s.parse::<u64>().ok())
-                .unwrap_or(0);
+#[cfg(target_os = "windows")]
+fn download_and_install_qq(installer: &QqInstaller) -> bool {
+    // 没有校验值时不运行未经校验的可执行文件，除非用户明确选择跳过
+    if installer.sha256.is_none() && !installer.allow_unverified {
+        eprintln!("没有 QQ 安装包的 SHA-256，无法校验下载内容，已取消安装");
+        if installer.url == QQ_DOWNLOAD_URL {
+            eprintln!("请使用 --qq-sha256 指定内置安装包的校验值");
+        } else {
+            eprintln!("请使用 --qq-sha256 指定校验值，或使用 --qq-no-verify 跳过校验（不推荐）");
+        }
+        return false;
+    }
+    println!("正在下载 QQ: {}", installer.url);
 
-            let mut file = match File::create(&temp_file) {
-                Ok(f) => f,
-                Err(e) => {
-                    eprintln!("创建临时文件失败: {}", e);
-                    return false;
-                }
-            };
+    // 按安装包文件名保存，下载中断后再次运行时可以续传
+    let file_name = installer
+        .url
+        .rsplit('/')
+        .next()
+        .filter(|n| n.ends_with(".exe"))
+        .unwrap_or("QQ_Setup.exe");
+    let temp_file = env::temp_dir().join(file_name);
 
-            let mut reader = resp.into_reader();
-            let mut buffer = [0u8; 65536];
-            let mut downloaded: u64 = 0;
+    let downloaded = http::download_resumable(
+        &installer.url,
+        &temp_file,
+        Duration::from_secs(300),
+        updater::print_download_progress,
+    );
+    println!();
+    if let Err(e) = downloaded {
+        eprintln!("下载失败: {}", e);
+        return false;
+    }
 
-            loop {
-                match std::io::Read::read(&mut reader, &mut buffer) {
-                    Ok(0) => break,
-                    Ok(n) => {
-                        if file.write_all(&buffer[..n]).is_err() {
-                            eprintln!("写入文件失败");
-                            return false;
-                        }
-                        downloaded += n as u64;
-                        if total_size > 0 {
-                            print!(
-                                "\r下载进度: {:.1} MB / {:.1} MB ({:.0}%)",
-                                downloaded as f64 / 1024.0 / 1024.0,
-                                total_size as f64 / 1024.0 / 1024.0,
-                                downloaded as f64 / total_size as f64 * 100.0
-                            );
-                            let _ = std::io::stdout().flush();
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("\n下载失败: {}", e);
-                        return false;
-                    }
-                }
+    // 校验通过后才运行安装程序
+    match installer.sha256 {
+        Some(ref expected) => match updater::sha256_file(&temp_file) {
+            Ok(actual) if actual == *expected => verbose!("QQ 安装包校验通过"),
+            Ok(actual) => {
+                eprintln!("QQ 安装包校验和不匹配 (sha256): 期望 {}，实际 {}", expected, actual);
+                eprintln!("已取消安装，请确认下载地址可信");
+                let _ = fs::remove_file(&temp_file);
+                return false;
             }
-            println!();
-
-            println!("正在安装 QQ（静默安装）...");
-            match Command::new(&temp_file).arg("/S").status() {
-                Ok(status) => {
-                    let _ = fs::remove_file(&temp_file);
-                    status.success()
-                }
-                Err(e) => {
-                    eprintln!("启动安装程序失败: {}", e);
-                    let _ = fs::remove_file(&temp_file);
-                    false
-                }
+            Err(e) => {
+                eprintln!("读取 QQ 安装包失败: {}", e);
+                let _ = fs::remove_file(&temp_file);
+                return false;
             }
-        }
+        },
+        None => warning!("已指定 --qq-no-verify，跳过 QQ 安装包校验"),
+    }
+
+    println!("正在安装 QQ（静默安装）...");
+    let result = Command::new(&temp_file).arg("/S").status();
+    let _ = fs::remove_file(&temp_file);
+    match result {
+        Ok(status) => status.success(),
         Err(e) => {
-            eprintln!("下载失败: {}", e);
+            eprintln!("启动安装程序失败: {}", e);
             false
         }
     }
 }
 
 #[cfg(not(target_os = "windows"))]
-fn download_and_install_qq() -> bool {
+fn download_and_install_qq(_installer: &QqInstaller) -> bool {
     eprintln!("QQ 自动安装仅支持 Windows");
     false
 }
 
-fn copy_dir_recursive(src: &Path, dst: &Path) -> std::io::Result<()> {
-    if !dst.exists() {
-        fs::create_dir_all(dst)?;
-    }
-
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let src_path = entry.path();
-        let dst_path = dst.join(entry.file_name());
-
-        if src_path.is_dir() {
-            copy_dir_recursive(&src_path, &dst_path)?;
-        } else {
-            fs::copy(&src_path, &dst_path)?;
-        }
-    }
-    Ok(())
-}
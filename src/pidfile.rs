@@ -0,0 +1,106 @@
+//! 程序目录下的 `llbot-cli.pid`，防止同一目录重复启动多个实例；
+//! 指定 `--account` 时每个账号使用各自的 `llbot-cli.<uin>.pid`
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+const PID_FILE_STEM: &str = "llbot-cli";
+
+/// 本进程写入的 PID 文件，退出时删除
+static PID_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// PID 文件路径
+pub fn path(exe_dir: &Path, account: Option<&str>) -> PathBuf {
+    match account {
+        Some(uin) => exe_dir.join(format!("{}.{}.pid", PID_FILE_STEM, uin)),
+        None => exe_dir.join(format!("{}.pid", PID_FILE_STEM)),
+    }
+}
+
+/// PID 文件中记录的进程仍在运行时返回其 PID；文件不存在、内容无效或进程已退出时返回 None
+pub fn running_instance(exe_dir: &Path, account: Option<&str>) -> Option<u32> {
+    let content = fs::read_to_string(path(exe_dir, account)).ok()?;
+    let pid = content.trim().parse::<u32>().ok()?;
+    if pid == std::process::id() {
+        // --daemon 启动的后台进程，PID 文件由父进程预先写入
+        return None;
+    }
+    // 按当前程序的文件名比较，启动器被改名后仍能识别；PID 被其他程序复用时视为已退出
+    let own_stem = std::env::current_exe()
+        .ok()
+        .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().to_string()));
+    let alive = match (process_stem(pid), own_stem) {
+        (Some(name), Some(own)) => name.eq_ignore_ascii_case(&own),
+        _ => false,
+    };
+    if !alive {
+        verbose!("PID 文件中的进程 {} 已不存在，忽略", pid);
+    }
+    alive.then_some(pid)
+}
+
+/// 进程 `pid` 的程序文件名（不含扩展名），进程不存在时返回 None。
+/// Linux 下读取 /proc/<pid>/exe，路径中含空格也不受影响；无权读取或没有 /proc 时
+/// 改用 `ps -o comm=`（macOS 输出完整路径，Linux 输出截断到 15 字节的文件名）
+#[cfg(not(target_os = "windows"))]
+fn process_stem(pid: u32) -> Option<String> {
+    let stem = |program: &str| {
+        Path::new(program)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+    };
+    if let Ok(exe) = fs::read_link(format!("/proc/{}/exe", pid)) {
+        // 程序文件被更新替换后链接目标带有 " (deleted)" 后缀
+        let exe = exe.to_string_lossy().to_string();
+        return stem(exe.strip_suffix(" (deleted)").unwrap_or(&exe));
+    }
+
+    let output = Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "comm="])
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let program = stdout.trim();
+    if program.is_empty() {
+        return None;
+    }
+    stem(program)
+}
+
+#[cfg(target_os = "windows")]
+fn process_stem(pid: u32) -> Option<String> {
+    let output = Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // 没有匹配的进程时输出一行提示，不是 CSV
+    let name = stdout.lines().next()?.split(',').next()?.trim_matches('"');
+    let stem_len = name.len().checked_sub(".exe".len())?;
+    name.get(stem_len..)?
+        .eq_ignore_ascii_case(".exe")
+        .then(|| name[..stem_len].to_string())
+}
+
+/// 写入当前进程的 PID，之后由 [`release`] 删除
+pub fn write(exe_dir: &Path, account: Option<&str>) {
+    let path = path(exe_dir, account);
+    match fs::write(&path, format!("{}\n", std::process::id())) {
+        Ok(()) => *PID_PATH.lock().unwrap() = Some(path),
+        Err(e) => warning!("写入 PID 文件 {} 失败: {}", path.display(), e),
+    }
+}
+
+/// 删除本进程写入的 PID 文件；文件已被其他实例改写时保留
+pub fn release() {
+    let Some(path) = PID_PATH.lock().ok().and_then(|mut p| p.take()) else {
+        return;
+    };
+    let ours = fs::read_to_string(&path)
+        .is_ok_and(|c| c.trim() == std::process::id().to_string());
+    if ours {
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -1,15 +1,72 @@
 //! PMHQ HTTP API 客户端
 
+use crate::{http, logger};
 use serde::{Deserialize, Serialize};
-use std::io::{BufRead, BufReader};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// `call` 等普通请求的默认超时
+const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(5);
+/// SSE 单次连接的默认超时，到期后重新连接
+const DEFAULT_SSE_TIMEOUT: Duration = Duration::from_secs(300);
+/// SSE 重连的初始等待时间，每次失败翻倍
+const SSE_RETRY_INITIAL: Duration = Duration::from_secs(1);
+const SSE_RETRY_MAX: Duration = Duration::from_secs(30);
+/// 连续重连失败超过该时长后放弃监听
+const SSE_RETRY_GIVE_UP: Duration = Duration::from_secs(10 * 60);
+
+/// 连接 PMHQ 使用的主机，由 [`set_bind_address`] 设置，默认为 127.0.0.1
+static HOST: OnceLock<String> = OnceLock::new();
+
+/// 根据 `--bind` 的监听地址确定连接地址：0.0.0.0 / :: 改为对应的回环地址
+pub fn set_bind_address(addr: IpAddr) {
+    let host = match addr {
+        IpAddr::V4(a) if a.is_unspecified() => Ipv4Addr::LOCALHOST.to_string(),
+        IpAddr::V4(a) => a.to_string(),
+        IpAddr::V6(a) if a.is_unspecified() => format!("[{}]", Ipv6Addr::LOCALHOST),
+        IpAddr::V6(a) => format!("[{}]", a),
+    };
+    let _ = HOST.set(host);
+}
 
 #[derive(Clone)]
 pub struct PMHQClient {
-    base_url: String,
+    /// 可与其他客户端共享，PMHQ 实际监听的端口与请求的不同时由启动器更新
+    port: Arc<AtomicU16>,
     timeout: Duration,
+    sse_timeout: Duration,
+}
+
+/// `call` 失败的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum PmhqError {
+    /// 无法连接 PMHQ 或读取响应失败
+    Network(String),
+    /// 响应格式不符合预期
+    Protocol(String),
+    /// PMHQ 或 QQ 返回的错误信息
+    Application(String),
+}
+
+impl fmt::Display for PmhqError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PmhqError::Network(msg) | PmhqError::Protocol(msg) | PmhqError::Application(msg) => {
+                write!(f, "{}", msg)
+            }
+        }
+    }
+}
+
+impl From<PmhqError> for String {
+    fn from(e: PmhqError) -> Self {
+        e.to_string()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -18,15 +75,46 @@ pub struct SelfInfo {
     pub nickname: String,
 }
 
+/// 扫码登录过程中的状态
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginState {
+    Disconnected,
+    WaitingQrcode,
+    QrcodeShown,
+    /// 账号已在其他设备登录，QQ 拒绝了本次扫码，附带 QQ 返回的原因
+    LoggedInElsewhere(String),
+}
+
+impl fmt::Display for LoginState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginState::Disconnected => write!(f, "未连接到 PMHQ"),
+            LoginState::WaitingQrcode => write!(f, "已连接，等待二维码"),
+            LoginState::QrcodeShown => write!(f, "已显示二维码，等待扫码"),
+            LoginState::LoggedInElsewhere(reason) => {
+                write!(f, "该账号已在其他设备登录: {}", reason)
+            }
+        }
+    }
+}
+
+/// 本机登录过的账号（来自 QQ 的快速登录列表）
+#[derive(Debug, Clone)]
+pub struct CachedAccount {
+    pub uin: String,
+    pub nickname: String,
+    pub last_login: Option<String>,
+}
+
 #[derive(Serialize)]
-struct CallRequest {
+struct CallRequest<'a> {
     r#type: &'static str,
-    data: CallData,
+    data: CallData<'a>,
 }
 
 #[derive(Serialize)]
-struct CallData {
-    func: &'static str,
+struct CallData<'a> {
+    func: &'a str,
     args: Vec<serde_json::Value>,
 }
 
@@ -44,62 +132,115 @@ struct SSEData {
 
 impl PMHQClient {
     pub fn new(port: u16) -> Self {
+        Self::with_shared_port(Arc::new(AtomicU16::new(port)))
+    }
+
+    /// 每次请求时读取 `port` 的当前值
+    pub fn with_shared_port(port: Arc<AtomicU16>) -> Self {
         Self {
-            base_url: format!("http://127.0.0.1:{}", port),
-            timeout: Duration::from_secs(5),
+            port,
+            timeout: DEFAULT_CALL_TIMEOUT,
+            sse_timeout: DEFAULT_SSE_TIMEOUT,
         }
     }
 
+    fn base_url(&self) -> String {
+        let host = HOST.get().map_or("127.0.0.1", String::as_str);
+        format!("http://{}:{}", host, self.port.load(Ordering::Relaxed))
+    }
+
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.timeout = timeout;
         self
     }
 
-    fn call(&self, func: &'static str) -> Result<serde_json::Value, String> {
+    /// SSE 单次连接的超时，不影响整个登录阶段的截止时间
+    pub fn with_sse_timeout(mut self, timeout: Duration) -> Self {
+        self.sse_timeout = timeout;
+        self
+    }
+
+    fn call(&self, func: &str) -> Result<serde_json::Value, PmhqError> {
+        self.call_with_args(func, vec![])
+    }
+
+    /// 调用 PMHQ 中的函数并返回 `result` 字段，例如
+    /// `call_with_args("msgService.sendMsg", vec![...])`
+    pub fn call_with_args(
+        &self,
+        func: &str,
+        args: Vec<serde_json::Value>,
+    ) -> Result<serde_json::Value, PmhqError> {
         let payload = CallRequest {
             r#type: "call",
-            data: CallData { func, args: vec![] },
+            data: CallData { func, args },
         };
 
-        let body_str =
-            serde_json::to_string(&payload).map_err(|e| format!("序列化失败: {}", e))?;
+        let body_str = serde_json::to_string(&payload)
+            .map_err(|e| PmhqError::Protocol(format!("序列化失败: {}", e)))?;
 
-        let resp = ureq::post(&self.base_url)
+        let req = http::post(&self.base_url())
             .timeout(self.timeout)
-            .set("Content-Type", "application/json")
-            .send_string(&body_str)
-            .map_err(|e| format!("请求失败: {}", e))?;
+            .set("Content-Type", "application/json");
+        let resp = http::send_string(req, &body_str)
+            .map_err(|e| PmhqError::Network(format!("请求失败: {}", e)))?;
 
         let resp_str = resp
             .into_string()
-            .map_err(|e| format!("读取响应失败: {}", e))?;
+            .map_err(|e| PmhqError::Network(format!("读取响应失败: {}", e)))?;
 
-        let body: CallResponse =
-            serde_json::from_str(&resp_str).map_err(|e| format!("解析响应失败: {}", e))?;
+        let body: CallResponse = serde_json::from_str(&resp_str)
+            .map_err(|e| PmhqError::Protocol(format!("解析响应失败: {}", e)))?;
 
         if body.r#type != "call" {
-            return Err("响应类型错误".to_string());
+            return Err(PmhqError::Protocol("响应类型错误".to_string()));
         }
 
         let inner: serde_json::Value = if body.data.is_string() {
             serde_json::from_str(body.data.as_str().unwrap())
-                .map_err(|e| format!("解析内部数据失败: {}", e))?
+                .map_err(|e| PmhqError::Protocol(format!("解析内部数据失败: {}", e)))?
         } else {
             body.data
         };
 
+        if let Some(message) = application_error(&inner) {
+            return Err(PmhqError::Application(message));
+        }
+
         if let Some(result) = inner.get("result") {
             if let Some(s) = result.as_str() {
                 if s.contains("Error") {
-                    return Err(s.to_string());
+                    return Err(PmhqError::Application(s.to_string()));
                 }
             }
             Ok(result.clone())
         } else {
-            Err("响应缺少 result 字段".to_string())
+            Err(PmhqError::Protocol("响应缺少 result 字段".to_string()))
         }
     }
 
+    /// 探测 PMHQ API 是否仍在响应，只要收到 HTTP 响应就视为存活
+    pub fn ping(&self) -> bool {
+        let payload = CallRequest {
+            r#type: "call",
+            data: CallData {
+                func: "getSelfInfo",
+                args: vec![],
+            },
+        };
+        let Ok(body_str) = serde_json::to_string(&payload) else {
+            return false;
+        };
+
+        let req = http::post(&self.base_url())
+            .timeout(self.timeout)
+            .set("Content-Type", "application/json");
+        matches!(
+            http::send_string(req, &body_str),
+            Ok(_) | Err(ureq::Error::Status(..))
+        )
+    }
+
     pub fn get_self_info(&self) -> Result<SelfInfo, String> {
         let result = self.call("getSelfInfo")?;
 
@@ -128,6 +269,78 @@ impl PMHQClient {
         Ok(SelfInfo { uin, nickname })
     }
 
+    /// 使用保存的会话令牌登录，跳过扫码；PMHQ 不支持该接口或 QQ 拒绝时返回原因
+    pub fn login_with_token(&self, token: &str) -> Result<(), String> {
+        let result = match self.call_with_args(
+            "loginService.loginWithTicket",
+            vec![serde_json::Value::String(token.to_string())],
+        ) {
+            Ok(result) => result,
+            Err(e) if is_unsupported_call(&e) => {
+                return Err("当前 PMHQ 版本不支持令牌登录".to_string())
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // QQ 的登录接口以 result 为 "0" 表示成功，失败原因在 loginErrorInfo 中；
+        // 响应中没有 result 时无法判断成败，以能否获取账号信息为准
+        match result.get("result").and_then(json_string) {
+            None => self
+                .get_self_info()
+                .map(|_| ())
+                .map_err(|e| format!("响应中缺少登录结果，且无法确认已登录: {}", e)),
+            Some(code) if code == "0" => Ok(()),
+            Some(code) => {
+                let reason = result
+                    .get("loginErrorInfo")
+                    .and_then(|info| info.get("errMsg"))
+                    .and_then(|v| v.as_str())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| format!("错误码 {}", code));
+                Err(reason)
+            }
+        }
+    }
+
+    /// 获取本机登录过的账号列表；PMHQ 不支持该接口时返回 `Ok(None)`
+    pub fn get_login_list(&self) -> Result<Option<Vec<CachedAccount>>, String> {
+        let result = match self.call("loginService.getLoginList") {
+            Ok(result) => result,
+            Err(e) if is_unsupported_call(&e) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let list = result
+            .get("LocalLoginInfoList")
+            .or_else(|| result.get("localLoginInfoList"))
+            .unwrap_or(&result)
+            .as_array()
+            .ok_or("响应格式不正确")?;
+
+        let accounts = list
+            .iter()
+            .filter_map(|item| {
+                let uin = json_string(item.get("uin")?)?;
+                let nickname = item
+                    .get("nickName")
+                    .or_else(|| item.get("nickname"))
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string();
+                let last_login = ["lastLoginTime", "loginTime"]
+                    .iter()
+                    .find_map(|key| item.get(key).and_then(json_string));
+                Some(CachedAccount {
+                    uin,
+                    nickname,
+                    last_login,
+                })
+            })
+            .collect();
+        Ok(Some(accounts))
+    }
+
     pub fn request_qrcode(&self) -> Result<(), String> {
         let payload = CallRequest {
             r#type: "call",
@@ -140,49 +353,123 @@ impl PMHQClient {
         let body_str =
             serde_json::to_string(&payload).map_err(|e| format!("序列化失败: {}", e))?;
 
-        ureq::post(&self.base_url)
+        let req = http::post(&self.base_url())
             .timeout(self.timeout)
-            .set("Content-Type", "application/json")
-            .send_string(&body_str)
-            .map_err(|e| format!("请求二维码失败: {}", e))?;
+            .set("Content-Type", "application/json");
+        http::send_string(req, &body_str).map_err(|e| format!("请求二维码失败: {}", e))?;
 
         Ok(())
     }
 
+    /// `--dump-sse`：把 SSE 流中的每一行 `data:` 原样加上时间输出到 stderr，不做任何解析；
+    /// 连接失败或断开后重新连接，直到进程退出
+    pub fn dump_sse(&self) {
+        loop {
+            let req = http::get(&self.base_url()).set("Accept", "text/event-stream");
+            match http::call(req) {
+                Ok(resp) => {
+                    let reader = BufReader::new(resp.into_reader());
+                    for line in reader.split(b'\n').map_while(Result::ok) {
+                        let line = String::from_utf8_lossy(&line);
+                        let line = line.trim_end_matches('\r');
+                        if !line.starts_with("data:") {
+                            continue;
+                        }
+                        let message = format!("[sse {}] {}", logger::timestamp(), line);
+                        let _ = writeln!(std::io::stderr().lock(), "{}", message);
+                        logger::log_line(logger::Level::Info, &message);
+                    }
+                    verbose!("SSE 连接已断开，重新连接");
+                }
+                Err(e) => verbose!("连接 SSE 失败: {}", e),
+            }
+            thread::sleep(SSE_RETRY_INITIAL);
+        }
+    }
+
     /// 启动 SSE 监听，处理二维码和登录事件
-    pub fn start_sse_listener<F>(&self, logged_in: Arc<AtomicBool>, mut on_qrcode: F)
+    ///
+    /// 登录状态变化时调用 `on_state`。`cancelled` 置位后直接返回 `Ok`。给定 `deadline` 时，整个监听（包括所有重连）
+    /// 在截止时间后返回 `Err`，内容为最后的登录状态。连接失败时按指数退避重连，
+    /// 连续失败超过 10 分钟同样返回 `Err`
+    pub fn start_sse_listener<F, S>(
+        &self,
+        logged_in: Arc<AtomicBool>,
+        cancelled: Arc<AtomicBool>,
+        deadline: Option<Instant>,
+        mut on_qrcode: F,
+        mut on_state: S,
+    ) -> Result<(), LoginState>
     where
         F: FnMut(&str, &str) + Send + 'static,
+        S: FnMut(&LoginState) + Send + 'static,
     {
-        let url = self.base_url.clone();
+        let mut state = LoginState::Disconnected;
+        let mut backoff = SSE_RETRY_INITIAL;
+        let mut failing_since: Option<Instant> = None;
 
         loop {
-            if logged_in.load(Ordering::Relaxed) {
-                break;
+            if logged_in.load(Ordering::Relaxed) || cancelled.load(Ordering::Relaxed) {
+                return Ok(());
             }
 
-            match ureq::get(&url)
-                .timeout(Duration::from_secs(300))
-                .set("Accept", "text/event-stream")
-                .call()
-            {
+            // 单次连接的超时不超过剩余时间，保证重连不会突破总截止时间
+            let mut timeout = self.sse_timeout;
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(state);
+                }
+                timeout = timeout.min(remaining);
+            }
+
+            let req = http::get(&self.base_url())
+                .timeout(timeout)
+                .set("Accept", "text/event-stream");
+            match http::call(req) {
                 Ok(resp) => {
-                    let reader = BufReader::new(resp.into_reader());
-                    for line in reader.lines() {
-                        if logged_in.load(Ordering::Relaxed) {
-                            return;
+                    backoff = SSE_RETRY_INITIAL;
+                    failing_since = None;
+                    if state == LoginState::Disconnected {
+                        state = LoginState::WaitingQrcode;
+                        on_state(&state);
+                    }
+                    let mut reader = BufReader::new(resp.into_reader());
+                    let mut buf = Vec::new();
+                    loop {
+                        if logged_in.load(Ordering::Relaxed) || cancelled.load(Ordering::Relaxed) {
+                            return Ok(());
+                        }
+
+                        // 按字节读取到换行，避免单个非 UTF-8 字节断开整个连接
+                        buf.clear();
+                        match reader.read_until(b'\n', &mut buf) {
+                            Ok(0) => break,
+                            Ok(_) => {}
+                            Err(e) => {
+                                verbose!("SSE 读取中断: {}", e);
+                                break;
+                            }
                         }
 
-                        let line = match line {
-                            Ok(l) => l,
-                            Err(_) => break,
+                        if buf.last() != Some(&b'\n') {
+                            verbose!("SSE 流在行中途结束，丢弃 {} 字节", buf.len());
+                            break;
+                        }
+
+                        let line = match std::str::from_utf8(&buf) {
+                            Ok(l) => std::borrow::Cow::Borrowed(l),
+                            Err(_) => {
+                                verbose!("SSE 行包含非 UTF-8 字节，已按有损方式解码");
+                                String::from_utf8_lossy(&buf)
+                            }
                         };
+                        let line = line.trim_end_matches(['\r', '\n']);
 
-                        if !line.starts_with("data: ") {
+                        let Some(json_str) = line.strip_prefix("data: ") else {
                             continue;
-                        }
+                        };
 
-                        let json_str = &line[6..];
                         if let Ok(data) = serde_json::from_str::<SSEData>(json_str) {
                             // 处理二维码事件
                             if data.r#type.as_deref() == Some("nodeIKernelLoginListener") {
@@ -203,10 +490,17 @@ impl PMHQClient {
                                                 .unwrap_or("");
 
                                             if !qrcode_url.is_empty() {
+                                                state = LoginState::QrcodeShown;
+                                                on_state(&state);
                                                 on_qrcode(qrcode_url, png_base64);
                                             }
                                         }
                                     }
+
+                                    if let Some(reason) = logged_in_elsewhere(inner) {
+                                        state = LoginState::LoggedInElsewhere(reason);
+                                        on_state(&state);
+                                    }
                                 }
                             }
 
@@ -219,7 +513,7 @@ impl PMHQClient {
                                         == Some("onSessionInitComplete")
                                     {
                                         logged_in.store(true, Ordering::Relaxed);
-                                        return;
+                                        return Ok(());
                                     }
                                 }
                             }
@@ -227,15 +521,98 @@ impl PMHQClient {
                             // 处理 account_ready 事件
                             if data.r#type.as_deref() == Some("account_ready") {
                                 logged_in.store(true, Ordering::Relaxed);
-                                return;
+                                return Ok(());
                             }
                         }
                     }
                 }
-                Err(_) => {
-                    std::thread::sleep(Duration::from_secs(2));
+                Err(e) => {
+                    let since = *failing_since.get_or_insert_with(Instant::now);
+                    if since.elapsed() >= SSE_RETRY_GIVE_UP {
+                        verbose!("SSE 连续 {} 秒连接失败，停止重连: {}", since.elapsed().as_secs(), e);
+                        return Err(state);
+                    }
+                    verbose!("SSE 连接失败，{} 秒后重试: {}", backoff.as_secs(), e);
+
+                    let mut wait = backoff;
+                    backoff = (backoff * 2).min(SSE_RETRY_MAX);
+                    if let Some(deadline) = deadline {
+                        wait = wait.min(deadline.saturating_duration_since(Instant::now()));
+                    }
+                    std::thread::sleep(wait);
                 }
             }
         }
     }
 }
+
+fn json_string(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) if !s.is_empty() => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// PMHQ 中不存在对应函数时的报错
+fn is_unsupported_call(error: &PmhqError) -> bool {
+    let PmhqError::Application(message) = error else {
+        return false;
+    };
+    // 只匹配明确表示函数不存在的报错，"Cannot read properties of undefined" 等运行时错误照常返回
+    let message = message.to_ascii_lowercase();
+    ["is not a function", "method not found"]
+        .iter()
+        .any(|k| message.contains(k))
+}
+
+/// 识别 PMHQ 返回的结构化错误：非空的 `error` 字段，或不为 0 的 `code` 字段
+fn application_error(inner: &serde_json::Value) -> Option<String> {
+    if let Some(error) = inner.get("error").filter(|e| !e.is_null()) {
+        let message = ["message", "msg", "errMsg"]
+            .iter()
+            .find_map(|k| error.get(k).and_then(|v| v.as_str()))
+            .or_else(|| error.as_str())
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| error.to_string());
+        return Some(message);
+    }
+
+    let code = inner.get("code")?;
+    if code.as_i64() == Some(0) || code.as_str() == Some("0") || code.is_null() {
+        return None;
+    }
+    let message = ["message", "msg", "errMsg"]
+        .iter()
+        .find_map(|k| inner.get(k).and_then(|v| v.as_str()))
+        .filter(|s| !s.is_empty());
+    Some(match message {
+        Some(message) => format!("{} (错误码 {})", message, code),
+        None => format!("错误码 {}", code),
+    })
+}
+
+/// 识别登录监听器中因"账号已在其他设备登录"而失败的事件，返回 QQ 给出的原因
+fn logged_in_elsewhere(event: &serde_json::Value) -> Option<String> {
+    let sub_type = event.get("sub_type")?.as_str()?;
+    if !matches!(sub_type, "onLoginFailed" | "onQRCodeSessionFailed") {
+        return None;
+    }
+
+    let data = event.get("data")?;
+    let message = ["errMsg", "tipsDesc", "tipsTitle"]
+        .iter()
+        .find_map(|key| {
+            data.get(key)
+                .or_else(|| data.get("loginErrorInfo").and_then(|e| e.get(key)))
+                .and_then(|v| v.as_str())
+        })
+        .unwrap_or("");
+
+    const KEYWORDS: &[&str] = &["其他设备", "已在", "另一", "another device"];
+    if KEYWORDS.iter().any(|k| message.contains(k)) {
+        Some(message.to_string())
+    } else {
+        None
+    }
+}
@@ -0,0 +1,97 @@
+//! 检查 QQ 版本是否在 LLBot 支持的范围内
+//!
+//! LLBot 只适配特定版本的 QQNT，版本不匹配时常见的表现是登录后无响应或反复崩溃，
+//! 因此启动前读取 QQ 版本并与 `bin/llbot/package.json` 中的 `supportedQQVersions` 比较。
+
+use std::fs;
+use std::path::Path;
+
+/// 注册表中 QQ 卸载信息的位置，查找 QQ 路径和读取版本共用
+#[cfg(target_os = "windows")]
+pub const UNINSTALL_KEY: &str = r"SOFTWARE\WOW6432Node\Microsoft\Windows\CurrentVersion\Uninstall\QQ";
+
+/// 版本不匹配时发出警告；读取不到 QQ 版本或 LLBot 未声明支持的版本时只输出诊断信息
+pub fn check(qq_path: &Path, llbot_dir: &Path) {
+    let supported = supported_versions(llbot_dir);
+    if supported.is_empty() {
+        verbose!("llbot.js 未声明支持的 QQ 版本，跳过版本检查");
+        return;
+    }
+    let Some(version) = detect(qq_path) else {
+        verbose!("无法读取 {} 的版本，跳过版本检查", qq_path.display());
+        return;
+    };
+    verbose!("QQ 版本: {}，LLBot 支持: {}", version, supported.join(", "));
+    if !supported.iter().any(|v| matches(v, &version)) {
+        warning!(
+            "QQ 版本 {} 不在当前 LLBot 支持的版本 ({}) 中，可能无法登录或运行异常，请安装对应版本的 QQ 或运行 `llbot --update`",
+            version,
+            supported.join(", ")
+        );
+    }
+}
+
+/// `supportedQQVersions` 可以是字符串或字符串数组
+fn supported_versions(llbot_dir: &Path) -> Vec<String> {
+    let json = fs::read_to_string(llbot_dir.join("package.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok());
+    match json.as_ref().and_then(|j| j.get("supportedQQVersions")) {
+        Some(serde_json::Value::String(v)) => vec![v.clone()],
+        Some(serde_json::Value::Array(list)) => list
+            .iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// QQNT 各平台的版本号前缀不同（Windows 9.9.x、Linux 3.2.x），但末尾的构建号一致，
+/// 因此双方都带构建号时只比较构建号，只写构建号的声明（如 "28418"）也能匹配
+fn matches(supported: &str, version: &str) -> bool {
+    let build = |v: &str| v.rsplit_once('-').map_or(v, |(_, build)| build).to_string();
+    supported == version || build(supported) == build(version)
+}
+
+/// Windows 下读取注册表卸载信息中的 DisplayVersion；`qq_path` 不是注册表中记录的那份安装时
+/// 无法得知其版本
+#[cfg(target_os = "windows")]
+fn detect(qq_path: &Path) -> Option<String> {
+    use winreg::enums::*;
+    use winreg::RegKey;
+
+    let key = RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey(UNINSTALL_KEY).ok()?;
+    let uninstall_path: String = key.get_value("UninstallString").ok()?;
+    let installed_dir = Path::new(uninstall_path.trim_matches('"')).parent()?;
+    if !qq_path.parent().is_some_and(|dir| same_path(dir, installed_dir)) {
+        verbose!("{} 不是注册表中记录的 QQ 安装", qq_path.display());
+        return None;
+    }
+    key.get_value("DisplayVersion").ok()
+}
+
+#[cfg(target_os = "windows")]
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Linux / macOS 下读取安装包内 resources/app/package.json 的 version；
+/// `/usr/bin/qq` 等符号链接先解析到实际的安装目录
+#[cfg(not(target_os = "windows"))]
+fn detect(qq_path: &Path) -> Option<String> {
+    let exe = qq_path.canonicalize().ok()?;
+    let dir = exe.parent()?;
+    // Linux: /opt/QQ/qq -> /opt/QQ/resources/app；macOS: QQ.app/Contents/MacOS/QQ -> QQ.app/Contents/Resources/app
+    let candidates = [
+        dir.join("resources/app/package.json"),
+        dir.parent()?.join("Resources/app/package.json"),
+    ];
+    candidates.iter().find_map(|path| {
+        let content = fs::read_to_string(path).ok()?;
+        let json: serde_json::Value = serde_json::from_str(&content).ok()?;
+        json.get("version")?.as_str().map(String::from)
+    })
+}
@@ -3,39 +3,83 @@
 use base64::Engine;
 use qrcode::QrCode;
 use std::fs;
+use std::io::Write;
 use std::path::Path;
 
-/// 在终端显示二维码（紧凑模式，类似 segno 的 compact=True）
-pub fn print_qrcode_terminal(url: &str) {
-    let code = match QrCode::new(url.as_bytes()) {
-        Ok(c) => c,
+/// 二维码在终端中的绘制方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QrStyle {
+    /// 半块字符，亮色字符表示白色模块，适合深色背景的终端
+    Block,
+    /// 半块字符，亮色字符表示黑色模块，适合浅色背景的终端
+    Invert,
+    /// 用 ANSI 背景色直接绘制黑白模块，不受终端配色影响
+    Ansi,
+}
+
+impl QrStyle {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "block" => Some(QrStyle::Block),
+            "invert" => Some(QrStyle::Invert),
+            "ansi" => Some(QrStyle::Ansi),
+            _ => None,
+        }
+    }
+}
+
+/// 在终端显示二维码
+///
+/// 整体拼好后一次性输出，二维码图案不会写入日志文件
+pub fn print_qrcode_terminal(url: &str, style: QrStyle) {
+    let rendered = match style {
+        QrStyle::Block => render_qrcode_terminal(url, false),
+        QrStyle::Invert => render_qrcode_terminal(url, true),
+        QrStyle::Ansi => render_qrcode_ansi(url),
+    };
+    let rendered = match rendered {
+        Ok(r) => r,
         Err(e) => {
-            eprintln!("生成二维码失败: {}", e);
+            eprintln!("{}", e);
             return;
         }
     };
 
+    // 清屏
+    print!("\x1B[2J\x1B[H\n{}\n", rendered);
+    let _ = std::io::stdout().flush();
+}
+
+/// 把二维码以方块字符写入日志文件，供后台运行时从日志中取回
+pub fn log_qrcode(url: &str) {
+    match render_qrcode_terminal(url, false) {
+        Ok(rendered) => {
+            crate::logger::log_line(crate::logger::Level::Info, &format!("登录二维码:\n{}", rendered))
+        }
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// 紧凑模式（类似 segno 的 compact=True），`invert` 为 true 时黑白互换
+fn render_qrcode_terminal(url: &str, invert: bool) -> Result<String, String> {
+    let code = QrCode::new(url.as_bytes()).map_err(|e| format!("生成二维码失败: {}", e))?;
+
     let width = code.width();
     let colors = code.to_colors();
-
-    // 清屏
-    print!("\x1B[2J\x1B[H");
-    println!();
+    let mut out = String::new();
 
     // 使用 Unicode 半块字符实现紧凑显示
     // ▀ (上半块), ▄ (下半块), █ (全块), 空格
-    // 白色用 █，黑色用空格
+    // 白色用 █，黑色用空格；invert 时相反
+    let light = if invert { " " } else { "█" };
 
     // 上边距（白色）
-    print!("█");
-    for _ in 0..width + 2 {
-        print!("█");
-    }
-    println!("█");
+    out.push_str(&light.repeat(width + 4));
+    out.push('\n');
 
     // 每两行合并为一行
     for y in (0..width).step_by(2) {
-        print!("██"); // 左边距
+        out.push_str(&light.repeat(2)); // 左边距
         
         for x in 0..width {
             let top_idx = y * width + x;
@@ -49,24 +93,78 @@ pub fn print_qrcode_terminal(url: &str) {
             };
 
             // 白=亮, 黑=暗
-            match (top_dark, bottom_dark) {
-                (false, false) => print!("█"), // 上下都白
-                (true, true) => print!(" "),   // 上下都黑
-                (false, true) => print!("▀"),  // 上白下黑
-                (true, false) => print!("▄"),  // 上黑下白
-            }
+            out.push(match (top_dark != invert, bottom_dark != invert) {
+                (false, false) => '█', // 上下都白
+                (true, true) => ' ',   // 上下都黑
+                (false, true) => '▀',  // 上白下黑
+                (true, false) => '▄',  // 上黑下白
+            });
         }
         
-        println!("██"); // 右边距
+        out.push_str(&light.repeat(2)); // 右边距
+        out.push('\n');
     }
 
     // 下边距
-    print!("█");
-    for _ in 0..width + 2 {
-        print!("█");
+    out.push_str(&light.repeat(width + 4));
+    out.push('\n');
+    Ok(out)
+}
+
+/// 每个模块占两个字符宽、一行高，黑白分别用 `ESC[40m` / `ESC[47m` 背景色绘制，
+/// 四周保留 4 个模块的白色静区
+fn render_qrcode_ansi(url: &str) -> Result<String, String> {
+    let mut out = String::new();
+    for row in module_rows(url)? {
+        // 相同颜色的连续模块只输出一次控制符
+        let mut current = None;
+        for dark in row {
+            if current != Some(dark) {
+                out.push_str(if dark { "\x1B[40m" } else { "\x1B[47m" });
+                current = Some(dark);
+            }
+            out.push_str("  ");
+        }
+        out.push_str("\x1B[0m\n");
+    }
+    Ok(out)
+}
+
+/// 纯 ASCII 绘制：黑色模块为 `##`，白色模块为两个空格，不含任何控制符
+fn render_qrcode_ascii(url: &str) -> Result<String, String> {
+    let mut out = String::new();
+    for row in module_rows(url)? {
+        let line: String = row.iter().map(|&dark| if dark { "##" } else { "  " }).collect();
+        out.push_str(line.trim_end());
+        out.push('\n');
     }
-    println!("█");
-    println!();
+    Ok(out)
+}
+
+/// 逐行返回各模块是否为黑色，四周包含 4 个模块的白色静区
+fn module_rows(url: &str) -> Result<Vec<Vec<bool>>, String> {
+    const QUIET_ZONE: usize = 4;
+    let code = QrCode::new(url.as_bytes()).map_err(|e| format!("生成二维码失败: {}", e))?;
+
+    let width = code.width();
+    let colors = code.to_colors();
+    let size = width + QUIET_ZONE * 2;
+    let is_dark = |x: usize, y: usize| {
+        x >= QUIET_ZONE
+            && y >= QUIET_ZONE
+            && x < width + QUIET_ZONE
+            && y < width + QUIET_ZONE
+            && colors[(y - QUIET_ZONE) * width + (x - QUIET_ZONE)] == qrcode::Color::Dark
+    };
+    Ok((0..size)
+        .map(|y| (0..size).map(|x| is_dark(x, y)).collect())
+        .collect())
+}
+
+/// `--export-qr-ascii`：把纯 ASCII 二维码写入文本文件，可在任意终端中 `cat` 后扫码
+pub fn save_qrcode_ascii(url: &str, path: &Path) -> Result<(), String> {
+    let rendered = render_qrcode_ascii(url)?;
+    fs::write(path, rendered).map_err(|e| format!("保存文件失败: {}", e))
 }
 
 pub fn save_qrcode_image(png_base64: &str, save_path: &Path) -> Result<(), String> {
@@ -84,3 +182,44 @@ pub fn save_qrcode_image(png_base64: &str, save_path: &Path) -> Result<(), Strin
 
     Ok(())
 }
+
+/// 根据二维码内容在本地生成 PNG 图片
+pub fn render_qrcode_image(url: &str, save_path: &Path) -> Result<(), String> {
+    let code = QrCode::new(url.as_bytes()).map_err(|e| format!("生成二维码失败: {}", e))?;
+    let image = code
+        .render::<image::Luma<u8>>()
+        .min_dimensions(300, 300)
+        .build();
+    image
+        .save_with_format(save_path, image::ImageFormat::Png)
+        .map_err(|e| format!("保存文件失败: {}", e))
+}
+
+/// SVG 二维码的尺寸设置
+#[derive(Debug, Clone, Copy)]
+pub struct SvgOptions {
+    /// 每个模块的边长（像素）
+    pub module_size: u32,
+    /// 是否保留四周 4 个模块宽的静区
+    pub quiet_zone: bool,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            module_size: 8,
+            quiet_zone: true,
+        }
+    }
+}
+
+/// 根据二维码网址生成矢量的 SVG 文件
+pub fn save_qrcode_svg(qrcode_url: &str, path: &Path, opts: SvgOptions) -> Result<(), String> {
+    let code = QrCode::new(qrcode_url.as_bytes()).map_err(|e| format!("生成二维码失败: {}", e))?;
+    let svg = code
+        .render::<qrcode::render::svg::Color>()
+        .module_dimensions(opts.module_size, opts.module_size)
+        .quiet_zone(opts.quiet_zone)
+        .build();
+    fs::write(path, svg).map_err(|e| format!("保存文件失败: {}", e))
+}
@@ -0,0 +1,149 @@
+//! `--status-port`：在 127.0.0.1 上提供 `GET /status`，以 JSON 返回启动器的运行状态，供监控使用
+
+use crate::pmhq_client::PMHQClient;
+use command_group::GroupChild;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// 读取请求的超时，防止客户端不发送数据时阻塞其他请求
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+/// 请求头的最大长度
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+/// 没有连接时检查是否需要停止的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 状态接口读取的共享状态，由主循环在重启 pmhq 时更新
+pub struct LauncherState {
+    started: Instant,
+    port: Arc<AtomicU16>,
+    child: Arc<Mutex<Option<GroupChild>>>,
+    running: AtomicBool,
+    /// 每次重启 pmhq 都会换成新的登录标记
+    logged_in: Mutex<Arc<AtomicBool>>,
+    client: PMHQClient,
+}
+
+impl LauncherState {
+    pub fn set_running(&self, running: bool) {
+        self.running.store(running, Ordering::Relaxed);
+    }
+
+    pub fn set_logged_in(&self, logged_in: Arc<AtomicBool>) {
+        *self.logged_in.lock().unwrap() = logged_in;
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let running = self.running.load(Ordering::Relaxed);
+        let pid = running
+            .then(|| self.child.lock().ok()?.as_ref().map(|c| c.id()))
+            .flatten();
+        let logged_in = self.logged_in.lock().unwrap().load(Ordering::Relaxed);
+        // 只在已登录时查询，pmhq 无响应时 uin 为 null
+        let uin = (running && logged_in)
+            .then(|| self.client.get_self_info().ok())
+            .flatten()
+            .map(|info| info.uin);
+        serde_json::json!({
+            "pmhq_running": running,
+            "pmhq_pid": pid,
+            "logged_in": logged_in,
+            "uin": uin,
+            "port": self.port.load(Ordering::Relaxed),
+            "uptime_secs": self.started.elapsed().as_secs(),
+        })
+    }
+}
+
+pub struct StatusServer {
+    pub state: Arc<LauncherState>,
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl StatusServer {
+    /// 监听 127.0.0.1:`listen_port`，在后台线程中处理请求
+    pub fn start(
+        listen_port: u16,
+        port: Arc<AtomicU16>,
+        child: Arc<Mutex<Option<GroupChild>>>,
+        logged_in: Arc<AtomicBool>,
+        request_timeout: Duration,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, listen_port))?;
+        listener.set_nonblocking(true)?;
+
+        let state = Arc::new(LauncherState {
+            started: Instant::now(),
+            client: PMHQClient::with_shared_port(port.clone()).with_timeout(request_timeout),
+            port,
+            child,
+            running: AtomicBool::new(true),
+            logged_in: Mutex::new(logged_in),
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (state, stop) = (state.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Err(e) = handle_connection(stream, &state) {
+                                verbose!("处理状态请求失败: {}", e);
+                            }
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(POLL_INTERVAL)
+                        }
+                        Err(e) => {
+                            verbose!("接受状态请求失败: {}", e);
+                            thread::sleep(POLL_INTERVAL);
+                        }
+                    }
+                }
+            })
+        };
+        Ok(StatusServer { state, stop, handle })
+    }
+
+    /// 停止监听并等待后台线程结束
+    pub fn shutdown(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.handle.join();
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, state: &LauncherState) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+    let (status, body) = match (method, path.split('?').next().unwrap_or("")) {
+        ("GET", "/status") => ("200 OK", state.to_json().to_string()),
+        ("GET", _) => ("404 Not Found", r#"{"error":"not found"}"#.to_string()),
+        _ => ("405 Method Not Allowed", r#"{"error":"method not allowed"}"#.to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,46 @@
+//! systemd `Type=notify` 服务的就绪通知
+//!
+//! 只有 systemd 设置了 `NOTIFY_SOCKET` 环境变量时才会发送，其他情况下（包括非 Linux 平台）
+//! 所有函数都不做任何事。协议见 sd_notify(3)。
+
+/// 更新 `systemctl status` 中显示的状态
+pub fn status(message: &str) {
+    notify(&format!("STATUS={}", message));
+}
+
+/// 登录成功后通知 systemd 服务已就绪
+pub fn ready(message: &str) {
+    notify(&format!("READY=1\nSTATUS={}", message));
+}
+
+/// 开始停止子进程
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+#[cfg(target_os = "linux")]
+fn notify(state: &str) {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let path = path.to_string_lossy();
+    // 以 @ 开头的是抽象命名空间中的套接字
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(path.as_ref()),
+    };
+    let sent = addr.and_then(|addr| {
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(state.as_bytes(), &addr)
+    });
+    match sent {
+        Ok(_) => verbose!("sd_notify: {}", state.replace('\n', " ")),
+        Err(e) => verbose!("sd_notify 发送失败 ({}): {}", path, e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn notify(_state: &str) {}
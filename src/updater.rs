@@ -1,14 +1,24 @@
 //! 更新检查和下载模块
 
-use serde::Deserialize;
+use crate::color;
+use crate::fsutil;
+use crate::http;
+use command_group::CommandGroup;
+use serde::{Deserialize, Serialize};
+use base64::Engine;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::{BTreeMap, HashMap};
 use std::env::consts::{ARCH, OS};
+use std::ffi::OsString;
 use std::fs::{self, File};
-use std::io::{self, BufReader, Read, Write};
-use std::path::Path;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 const NPM_OFFICIAL_REGISTRY: &str = "https://registry.npmjs.org";
 const NPM_REGISTRY_MIRRORS: &[&str] = &[
@@ -17,14 +27,147 @@ const NPM_REGISTRY_MIRRORS: &[&str] = &[
     "https://mirrors.cloud.tencent.com/npm",
 ];
 
+/// 获取包信息等 registry 请求的默认超时，可用 `--mirror-timeout` 修改
 const UPDATE_TIMEOUT_SECS: u64 = 15;
+/// 包信息无法解析时在调试日志中记录的响应字节数
+const PARSE_ERROR_PREVIEW: usize = 64;
+/// 镜像竞速时同时进行的请求数
+const MIRROR_RACE_CONCURRENCY: usize = 4;
 const DOWNLOAD_TIMEOUT_SECS: u64 = 300;
+/// 更新后运行 `pmhq --version` 等待其退出的最长时间，仍在运行视为可以启动
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(5);
+/// 更新所需空间为解压后大小的倍数
+const DISK_SPACE_FACTOR: u64 = 3;
+pub const PENDING_DIR: &str = "pending";
+const MIRROR_CACHE_FILE: &str = ".mirror_cache";
+/// 缓存的镜像超过该时长后重新比较
+const MIRROR_CACHE_TTL_SECS: u64 = 24 * 60 * 60;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug)]
 struct NpmPackageInfo {
     version: String,
+    checksum: Option<Checksum>,
+    unpacked_size: Option<u64>,
+}
+
+/// npm 元数据中 `dist.integrity`（sha512）和 `dist.shasum`（sha1）给出的校验值
+#[derive(Debug, Clone)]
+pub struct Checksum {
+    integrity: Option<String>,
+    shasum: Option<String>,
+}
+
+impl Checksum {
+    fn from_dist(dist: &PackumentDist) -> Option<Self> {
+        if dist.integrity.is_none() && dist.shasum.is_none() {
+            return None;
+        }
+        Some(Self {
+            integrity: dist.integrity.clone(),
+            shasum: dist.shasum.clone(),
+        })
+    }
+
+    /// 优先校验 sha512 integrity，没有时退回 sha1 shasum
+    fn verify(&self, data: &[u8]) -> Result<(), String> {
+        let sha512 = self
+            .integrity
+            .as_deref()
+            .and_then(|i| i.split_whitespace().find_map(|h| h.strip_prefix("sha512-")));
+        if let Some(expected) = sha512 {
+            let actual = base64::engine::general_purpose::STANDARD.encode(Sha512::digest(data));
+            if actual != expected {
+                return Err(format!("校验和不匹配 (sha512): 期望 {}，实际 {}", expected, actual));
+            }
+            return Ok(());
+        }
+
+        if let Some(ref expected) = self.shasum {
+            let actual = to_hex(&Sha1::digest(data));
+            if !actual.eq_ignore_ascii_case(expected) {
+                return Err(format!("校验和不匹配 (sha1): 期望 {}，实际 {}", expected, actual));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// npm 的包元数据（packument），只解析需要的字段
+#[derive(Debug, Deserialize)]
+struct Packument {
+    #[serde(rename = "dist-tags")]
+    dist_tags: DistTags,
+    #[serde(default)]
+    versions: HashMap<String, PackumentVersion>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DistTags {
+    latest: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PackumentVersion {
+    dist: Option<PackumentDist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PackumentDist {
+    tarball: Option<String>,
+    shasum: Option<String>,
+    integrity: Option<String>,
+    /// 解压后的总大小（字节），用于更新前估算所需的磁盘空间
+    #[serde(rename = "unpackedSize")]
+    unpacked_size: Option<u64>,
 }
 
+impl Packument {
+    /// 选出要更新到的版本：默认为 `latest` 标签，它是预发布版本时改用最高的正式版；
+    /// `--allow-prerelease` 时取所有版本中最高的一个
+    fn into_update_info(self) -> NpmPackageInfo {
+        let allow_prerelease = ALLOW_PRERELEASE.load(Ordering::Relaxed);
+        let latest = &self.dist_tags.latest;
+        let version = if !allow_prerelease && !is_prerelease(latest) {
+            latest.clone()
+        } else {
+            self.versions
+                .keys()
+                .chain([latest])
+                .filter(|v| allow_prerelease || !is_prerelease(v))
+                .max_by(|a, b| cmp_versions(a, b))
+                .unwrap_or(latest)
+                .clone()
+        };
+
+        let dist = self.versions.get(&version).and_then(|v| v.dist.as_ref());
+        let checksum = dist.and_then(Checksum::from_dist);
+        let unpacked_size = dist.and_then(|d| d.unpacked_size);
+        NpmPackageInfo { version, checksum, unpacked_size }
+    }
+}
+
+/// `--allow-prerelease`：允许更新到预发布版本
+static ALLOW_PRERELEASE: AtomicBool = AtomicBool::new(false);
+
+/// `--mirror-timeout`：单个 registry 请求的超时秒数
+static MIRROR_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(UPDATE_TIMEOUT_SECS);
+
+/// 用户在 llbot.toml 中指定的 registry，优先于官方源和内置镜像
+static PREFERRED_REGISTRY: Mutex<Option<String>> = Mutex::new(None);
+
+/// `--registry` 指定的 registry，设置后只使用它，不再尝试官方源和镜像
+static FORCED_REGISTRY: Mutex<Option<String>> = Mutex::new(None);
+
+/// 上次竞速胜出的镜像缓存文件，由 `run_update` / `run_check_update` 设置
+static MIRROR_CACHE_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// `包名@版本` -> (来源 registry, dist.tarball)
+static TARBALL_CACHE: Mutex<Option<HashMap<String, (String, String)>>> = Mutex::new(None);
+
 #[derive(Debug)]
 pub struct UpdateInfo {
     pub name: String,
@@ -32,6 +175,9 @@ pub struct UpdateInfo {
     pub latest_version: String,
     pub has_update: bool,
     pub tarball_url: Option<String>,
+    pub checksum: Option<Checksum>,
+    /// 元数据中的解压后大小，未提供时为 None
+    pub unpacked_size: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -70,130 +216,359 @@ fn get_platform_info() -> (&'static str, &'static str) {
     (os_name, arch_name)
 }
 
+fn fetch_packument(registry: &str, encoded_name: &str) -> Option<Packument> {
+    let url = format!("{}/{}", registry, encoded_name);
+    // 精简版元数据同样包含 dist.tarball，体积比完整 packument 小得多
+    let req = http::get(&url)
+        .timeout(mirror_timeout())
+        .set("Accept", "application/vnd.npm.install-v1+json; q=1.0, application/json; q=0.8");
+    let resp = match http::call(req) {
+        Ok(resp) if resp.status() == 200 => resp,
+        _ => return None,
+    };
+    // 响应为 gzip 时由 ureq 解码；仍无法解析时记录开头的内容，便于区分镜像故障和格式问题
+    let mut body = Vec::new();
+    if let Err(e) = resp.into_reader().read_to_end(&mut body) {
+        verbose!("读取 {} 的响应失败: {}", url, e);
+        return None;
+    }
+    match serde_json::from_slice::<Packument>(&body) {
+        Ok(packument) => Some(packument),
+        Err(e) => {
+            let head = &body[..body.len().min(PARSE_ERROR_PREVIEW)];
+            verbose!(
+                "解析 {} 的包信息失败: {}，响应开头 {} 字节: {:?}",
+                url,
+                e,
+                head.len(),
+                String::from_utf8_lossy(head)
+            );
+            None
+        }
+    }
+}
+
+fn cache_tarballs(package_name: &str, registry: &str, packument: &Packument) {
+    let mut cache = TARBALL_CACHE.lock().unwrap();
+    let cache = cache.get_or_insert_with(HashMap::new);
+    for (version, meta) in &packument.versions {
+        if let Some(tarball) = meta.dist.as_ref().and_then(|d| d.tarball.clone()) {
+            cache.insert(
+                format!("{}@{}", package_name, version),
+                (registry.to_string(), tarball),
+            );
+        }
+    }
+}
+
+fn cached_tarball(package_name: &str, version: &str) -> Option<(String, String)> {
+    TARBALL_CACHE
+        .lock()
+        .unwrap()
+        .as_ref()?
+        .get(&format!("{}@{}", package_name, version))
+        .cloned()
+}
+
+pub fn set_preferred_registry(registry: &str) {
+    *PREFERRED_REGISTRY.lock().unwrap() = Some(registry.trim_end_matches('/').to_string());
+}
+
+fn preferred_registry() -> Option<String> {
+    PREFERRED_REGISTRY.lock().unwrap().clone()
+}
+
+/// 只使用指定的 registry，地址必须以 http:// 或 https:// 开头
+pub fn set_registry(registry: &str) -> Result<(), String> {
+    if !registry.starts_with("http://") && !registry.starts_with("https://") {
+        return Err(format!("registry 地址需要以 http:// 或 https:// 开头: {}", registry));
+    }
+    *FORCED_REGISTRY.lock().unwrap() = Some(registry.trim_end_matches('/').to_string());
+    Ok(())
+}
+
+pub fn set_allow_prerelease(allow: bool) {
+    ALLOW_PRERELEASE.store(allow, Ordering::Relaxed);
+}
+
+pub fn set_mirror_timeout(timeout: Duration) {
+    MIRROR_TIMEOUT_SECS.store(timeout.as_secs().max(1), Ordering::Relaxed);
+}
+
+fn mirror_timeout() -> Duration {
+    Duration::from_secs(MIRROR_TIMEOUT_SECS.load(Ordering::Relaxed))
+}
+
+fn forced_registry() -> Option<String> {
+    FORCED_REGISTRY.lock().unwrap().clone()
+}
+
+fn set_mirror_cache_dir(exe_dir: &Path) {
+    *MIRROR_CACHE_PATH.lock().unwrap() = Some(exe_dir.join(MIRROR_CACHE_FILE));
+}
+
+/// 读取缓存的镜像，格式为 `<registry>\n<unix 秒>`，过期或不存在时返回 None
+fn cached_mirror() -> Option<String> {
+    let path = MIRROR_CACHE_PATH.lock().unwrap().clone()?;
+    let content = fs::read_to_string(path).ok()?;
+    let mut lines = content.lines();
+    let registry = lines.next()?.trim().to_string();
+    let saved_at: u64 = lines.next()?.trim().parse().ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    if registry.is_empty() || now.saturating_sub(saved_at) > MIRROR_CACHE_TTL_SECS {
+        return None;
+    }
+    Some(registry)
+}
+
+fn save_mirror_cache(registry: &str) {
+    let Some(path) = MIRROR_CACHE_PATH.lock().unwrap().clone() else {
+        return;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    if let Err(e) = fs::write(&path, format!("{}\n{}\n", registry, now)) {
+        verbose!("写入镜像缓存失败: {}", e);
+    }
+}
+
 fn fetch_package_info(package_name: &str) -> Result<NpmPackageInfo, String> {
     let encoded_name = package_name.replace("/", "%2F");
     
-    // 先尝试官方源
-    let url = format!("{}/{}/latest", NPM_OFFICIAL_REGISTRY, encoded_name);
-    match ureq::get(&url)
-        .timeout(Duration::from_secs(UPDATE_TIMEOUT_SECS))
-        .call()
-    {
-        Ok(resp) if resp.status() == 200 => {
-            if let Ok(info) = resp.into_json::<NpmPackageInfo>() {
-                return Ok(info);
-            }
-        }
-        _ => {}
+    if let Some(registry) = forced_registry() {
+        let packument = fetch_packument(&registry, &encoded_name)
+            .ok_or_else(|| format!("无法从 {} 获取 {} 的包信息", registry, package_name))?;
+        cache_tarballs(package_name, &registry, &packument);
+        return Ok(packument.into_update_info());
     }
     
-    // 官方源失败，并发尝试镜像源
-    let (tx, rx) = mpsc::channel();
+    if let Some(registry) = preferred_registry() {
+        if let Some(packument) = fetch_packument(&registry, &encoded_name) {
+            cache_tarballs(package_name, &registry, &packument);
+            return Ok(packument.into_update_info());
+        }
+    }
     
-    for mirror in NPM_REGISTRY_MIRRORS {
-        let tx = tx.clone();
-        let url = format!("{}/{}/latest", mirror, encoded_name);
-        thread::spawn(move || {
-            if let Ok(resp) = ureq::get(&url)
-                .timeout(Duration::from_secs(UPDATE_TIMEOUT_SECS))
-                .call()
-            {
-                if resp.status() == 200 {
-                    if let Ok(info) = resp.into_json::<NpmPackageInfo>() {
-                        let _ = tx.send(Some(info));
-                        return;
-                    }
-                }
-            }
-            let _ = tx.send(None);
-        });
+    if let Some(registry) = cached_mirror() {
+        if let Some(packument) = fetch_packument(&registry, &encoded_name) {
+            verbose!("使用缓存的镜像: {}", registry);
+            cache_tarballs(package_name, &registry, &packument);
+            return Ok(packument.into_update_info());
+        }
     }
     
-    drop(tx);
+    // 先尝试官方源
+    if let Some(packument) = fetch_packument(NPM_OFFICIAL_REGISTRY, &encoded_name) {
+        cache_tarballs(package_name, NPM_OFFICIAL_REGISTRY, &packument);
+        return Ok(packument.into_update_info());
+    }
     
-    for result in rx {
-        if let Some(info) = result {
-            return Ok(info);
-        }
+    // 官方源失败，并发尝试镜像源
+    if let Some((mirror, packument)) =
+        race_mirrors(move |mirror| fetch_packument(mirror, &encoded_name))
+    {
+        save_mirror_cache(mirror);
+        cache_tarballs(package_name, mirror, &packument);
+        return Ok(packument.into_update_info());
     }
     
     Err(format!("无法获取 {} 的包信息", package_name))
 }
 
+/// 所有 registry 都无法访问时，在交互模式下请用户输入一个 registry 地址；
+/// 地址可用时改为只使用它并写入镜像缓存，返回 true。直接回车或非交互模式时返回 false
+fn prompt_custom_registry(package_name: &str) -> bool {
+    if forced_registry().is_some() || !io::stdin().is_terminal() {
+        return false;
+    }
+    println!();
+    println!("{}", color::yellow("官方源和内置镜像均无法访问"));
+    let encoded_name = package_name.replace("/", "%2F");
+    loop {
+        print!("请输入可用的 npm registry 地址（直接回车跳过）: ");
+        io::stdout().flush().ok();
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).is_err() {
+            return false;
+        }
+        let registry = input.trim();
+        if registry.is_empty() {
+            return false;
+        }
+        if let Err(e) = set_registry(registry) {
+            println!("{}", e);
+            continue;
+        }
+        let registry = registry.trim_end_matches('/');
+        if fetch_packument(registry, &encoded_name).is_some() {
+            println!("{}", color::green(&format!("{} 可用，将使用它检查更新", registry)));
+            save_mirror_cache(registry);
+            return true;
+        }
+        *FORCED_REGISTRY.lock().unwrap() = None;
+        println!("无法从 {} 获取 {} 的包信息，请重新输入", registry, package_name);
+    }
+}
+
 fn check_version_exists(package_name: &str, version: &str, registry: &str) -> bool {
     let encoded_name = package_name.replace("/", "%2F");
     let url = format!("{}/{}/{}", registry, encoded_name, version);
     
-    ureq::get(&url)
-        .timeout(Duration::from_secs(UPDATE_TIMEOUT_SECS))
-        .call()
+    http::call(http::get(&url).timeout(mirror_timeout()))
         .map(|r| r.status() == 200)
         .unwrap_or(false)
 }
 
 fn get_best_download_registry(package_name: &str, version: &str) -> String {
-    let (tx, rx) = mpsc::channel();
+    if let Some(registry) = forced_registry() {
+        return registry;
+    }
+    for registry in preferred_registry().into_iter().chain(cached_mirror()) {
+        if check_version_exists(package_name, version, &registry) {
+            return registry;
+        }
+    }
     
-    for mirror in NPM_REGISTRY_MIRRORS {
-        let tx = tx.clone();
-        let mirror = mirror.to_string();
-        let pkg = package_name.to_string();
-        let ver = version.to_string();
-        
+    let (pkg, ver) = (package_name.to_string(), version.to_string());
+    if let Some((mirror, ())) =
+        race_mirrors(move |mirror| check_version_exists(&pkg, &ver, mirror).then_some(()))
+    {
+        save_mirror_cache(mirror);
+        return mirror.to_string();
+    }
+    
+    NPM_OFFICIAL_REGISTRY.to_string()
+}
+
+/// 并发向内置镜像发出请求，返回第一个成功的结果。同时进行的请求不超过
+/// `MIRROR_RACE_CONCURRENCY` 个，每个请求受 `--mirror-timeout` 限制；得到结果后不再发起新的请求，
+/// 仍在进行的请求在超时内结束，结果被丢弃
+fn race_mirrors<T: Send + 'static>(
+    probe: impl Fn(&'static str) -> Option<T> + Send + Sync + 'static,
+) -> Option<(&'static str, T)> {
+    let queue = Arc::new(Mutex::new(NPM_REGISTRY_MIRRORS.iter().copied()));
+    let found = Arc::new(AtomicBool::new(false));
+    let probe = Arc::new(probe);
+    let (tx, rx) = mpsc::channel();
+
+    for _ in 0..MIRROR_RACE_CONCURRENCY.min(NPM_REGISTRY_MIRRORS.len()) {
+        let (queue, found, probe, tx) = (queue.clone(), found.clone(), probe.clone(), tx.clone());
         thread::spawn(move || {
-            if check_version_exists(&pkg, &ver, &mirror) {
-                let _ = tx.send(Some(mirror));
-            } else {
-                let _ = tx.send(None);
+            while !found.load(Ordering::Relaxed) {
+                let Some(mirror) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                if let Some(result) = probe(mirror) {
+                    found.store(true, Ordering::Relaxed);
+                    let _ = tx.send((mirror, result));
+                }
             }
         });
     }
-    
     drop(tx);
-    
-    for result in rx {
-        if let Some(registry) = result {
-            return registry;
-        }
+
+    // 所有线程都没有结果时发送端全部释放，recv 返回错误
+    let winner = rx.recv().ok();
+    found.store(true, Ordering::Relaxed);
+    if let Some((mirror, _)) = winner {
+        verbose!("镜像竞速胜出: {}", mirror);
     }
-    
-    NPM_OFFICIAL_REGISTRY.to_string()
+    winner
 }
 
 fn get_tarball_url(package_name: &str, version: &str) -> String {
     let best_registry = get_best_download_registry(package_name, version);
-    let pkg_short_name = package_name.split('/').last().unwrap_or(package_name);
-    format!("{}/{}/-/{}-{}.tgz", best_registry, package_name, pkg_short_name, version)
+    tarball_url_on(package_name, version, &best_registry)
+}
+
+fn tarball_url_on(package_name: &str, version: &str, registry: &str) -> String {
+    if let Some((source, tarball)) = cached_tarball(package_name, version) {
+        return rebase_tarball_url(&tarball, &source, registry);
+    }
+    
+    // 元数据中没有 dist.tarball 时才按 npm 的默认布局拼接
+    let pkg_short_name = package_name.rsplit('/').next().unwrap_or(package_name);
+    format!("{}/{}/-/{}-{}.tgz", registry, package_name, pkg_short_name, version)
 }
 
+/// 把 tarball 地址换到目标 registry，保留相对 registry 的路径
+fn rebase_tarball_url(tarball: &str, source: &str, target: &str) -> String {
+    let path = match tarball.strip_prefix(source.trim_end_matches('/')) {
+        Some(path) => path,
+        None => tarball
+            .split_once("://")
+            .and_then(|(_, rest)| rest.find('/').map(|i| &rest[i..]))
+            .unwrap_or(tarball),
+    };
+    format!("{}{}", target.trim_end_matches('/'), path)
+}
+
+/// `latest` 是否比 `current` 新
 fn compare_versions(current: &str, latest: &str) -> bool {
-    let parse_version = |v: &str| -> Vec<u32> {
-        v.trim_start_matches('v')
-            .trim_start_matches('V')
-            .split('.')
-            .filter_map(|s| s.split('-').next()?.parse().ok())
-            .collect()
+    cmp_versions(latest, current).is_gt()
+}
+
+/// 把版本号拆成数字部分和预发布标识，忽略前缀 v 和 `+` 之后的构建信息
+fn split_version(v: &str) -> (Vec<u64>, Vec<&str>) {
+    let v = v.trim_start_matches(['v', 'V']);
+    let v = v.split('+').next().unwrap_or(v);
+    let (core, pre) = match v.split_once('-') {
+        Some((core, pre)) => (core, pre.split('.').collect()),
+        None => (v, Vec::new()),
     };
+    let core = core.split('.').map(|p| p.parse().unwrap_or(0)).collect();
+    (core, pre)
+}
+
+fn is_prerelease(v: &str) -> bool {
+    !split_version(v).1.is_empty()
+}
+
+/// 按 semver 规则比较版本：先比数字部分，相同时正式版高于预发布版，
+/// 预发布标识逐段比较，纯数字的段按数值比较且低于字母段
+fn cmp_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+    
+    let (a_core, a_pre) = split_version(a);
+    let (b_core, b_pre) = split_version(b);
+    
+    for i in 0..a_core.len().max(b_core.len()) {
+        let ord = a_core.get(i).unwrap_or(&0).cmp(b_core.get(i).unwrap_or(&0));
+        if ord.is_ne() {
+            return ord;
+        }
+    }
     
-    let current_parts = parse_version(current);
-    let latest_parts = parse_version(latest);
-    
-    for i in 0..std::cmp::max(current_parts.len(), latest_parts.len()) {
-        let c = current_parts.get(i).copied().unwrap_or(0);
-        let l = latest_parts.get(i).copied().unwrap_or(0);
-        if l > c {
-            return true;
-        } else if l < c {
-            return false;
+    match (a_pre.is_empty(), b_pre.is_empty()) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Greater,
+        (false, true) => return Ordering::Less,
+        (false, false) => {}
+    }
+    for (x, y) in a_pre.iter().zip(&b_pre) {
+        let ord = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => x.cmp(y),
+        };
+        if ord.is_ne() {
+            return ord;
         }
     }
-    false
+    a_pre.len().cmp(&b_pre.len())
 }
 
 pub fn check_update(name: &str, package_name: &str, current_version: &str) -> UpdateInfo {
     match fetch_package_info(package_name) {
         Ok(info) => {
-            let has_update = compare_versions(current_version, &info.version);
+            // 当前是正式版时，除非指定 --allow-prerelease，不会更新到预发布版本
+            let has_update = compare_versions(current_version, &info.version)
+                && (ALLOW_PRERELEASE.load(Ordering::Relaxed)
+                    || !is_prerelease(&info.version)
+                    || is_prerelease(current_version));
             let tarball_url = if has_update {
                 Some(get_tarball_url(package_name, &info.version))
             } else {
@@ -205,16 +580,20 @@ pub fn check_update(name: &str, package_name: &str, current_version: &str) -> Up
                 latest_version: info.version,
                 has_update,
                 tarball_url,
+                checksum: info.checksum,
+                unpacked_size: info.unpacked_size,
             }
         }
         Err(e) => {
-            eprintln!("检查 {} 更新失败: {}", name, e);
+            warning!("检查 {} 更新失败: {}", name, e);
             UpdateInfo {
                 name: name.to_string(),
                 current_version: current_version.to_string(),
                 latest_version: "未知".to_string(),
                 has_update: false,
                 tarball_url: None,
+                checksum: None,
+                unpacked_size: None,
             }
         }
     }
@@ -238,6 +617,7 @@ pub fn get_local_version(exe_dir: &Path, component: &str) -> String {
     "未安装".to_string()
 }
 
+/// 查找正在运行的 LLBot 相关进程（llbot、pmhq、QQ 以及运行 llbot.js 的 node），不包括自身
 #[cfg(target_os = "windows")]
 pub fn check_running_processes() -> Vec<(String, u32)> {
     let mut running = Vec::new();
@@ -259,7 +639,9 @@ pub fn check_running_processes() -> Vec<(String, u32)> {
                 for target in &targets {
                     if name.eq_ignore_ascii_case(target) {
                         if let Ok(pid) = pid_str.parse::<u32>() {
-                            running.push((name.to_string(), pid));
+                            if pid != std::process::id() {
+                                running.push((name.to_string(), pid));
+                            }
                         }
                     }
                 }
@@ -271,7 +653,42 @@ pub fn check_running_processes() -> Vec<(String, u32)> {
 
 #[cfg(not(target_os = "windows"))]
 pub fn check_running_processes() -> Vec<(String, u32)> {
-    Vec::new()
+    let mut running = Vec::new();
+
+    // Linux 和 macOS 的 ps 都支持该格式，args 为完整命令行
+    let output = Command::new("ps")
+        .args(["-A", "-o", "pid=", "-o", "args="])
+        .output();
+
+    if let Ok(output) = output {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        for line in stdout.lines() {
+            let Some((pid_str, cmdline)) = line.trim_start().split_once(char::is_whitespace) else {
+                continue;
+            };
+            let Ok(pid) = pid_str.parse::<u32>() else {
+                continue;
+            };
+            if pid == std::process::id() {
+                continue;
+            }
+
+            let program = cmdline.split_whitespace().next().unwrap_or("");
+            let name = Path::new(program)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let matched = match name.as_str() {
+                "llbot" | "pmhq" | "qq" | "QQ" => true,
+                "node" => cmdline.contains("llbot.js"),
+                _ => false,
+            };
+            if matched {
+                running.push((name, pid));
+            }
+        }
+    }
+    running
 }
 
 #[cfg(target_os = "windows")]
@@ -292,87 +709,358 @@ pub fn kill_process(pid: u32) -> bool {
         .unwrap_or(false)
 }
 
-pub fn download_and_extract(tarball_url: &str, extract_dir: &Path) -> Result<(), String> {
+/// 地址中没有文件名时下载的压缩包使用的名称
+const DEFAULT_ARCHIVE_NAME: &str = "package.tgz";
+
+/// 是否为 `download_and_extract` 保存的压缩包：npm 的 `<包名>-<版本>.tgz` 或 [`DEFAULT_ARCHIVE_NAME`]，
+/// 供 `--clean` 区分下载残留和用户自己放置的压缩包
+pub fn is_download_archive(file_name: &str) -> bool {
+    let packages = ComponentPackages::for_current_platform();
+    file_name == DEFAULT_ARCHIVE_NAME
+        || [&packages.cli_package, &packages.pmhq_package, &packages.llbot_package]
+            .iter()
+            .filter_map(|pkg| file_name.strip_prefix(pkg.as_str())?.strip_prefix('-')?.strip_suffix(".tgz"))
+            .any(|version| version.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// 下载并解压 tarball；给定 `checksum` 时在写入磁盘前校验下载内容，
+/// 给定 `component` 时先确认包内有该组件的关键文件，否则不改动 `extract_dir` 中的已有文件
+pub fn download_and_extract(
+    tarball_url: &str,
+    extract_dir: &Path,
+    checksum: Option<&Checksum>,
+    component: Option<&str>,
+    verify: &dyn Fn() -> Result<(), String>,
+) -> Result<(), String> {
     println!("下载中: {}", tarball_url);
     
-    let resp = ureq::get(tarball_url)
-        .timeout(std::time::Duration::from_secs(DOWNLOAD_TIMEOUT_SECS))
-        .call()
-        .map_err(|e| format!("下载失败: {}", e))?;
-    
-    if resp.status() != 200 {
-        return Err(format!("HTTP 错误: {}", resp.status()));
-    }
-    
-    let content_length = resp.header("content-length")
-        .and_then(|s| s.parse::<usize>().ok())
-        .unwrap_or(0);
-    
-    let mut data = Vec::with_capacity(content_length);
-    resp.into_reader()
-        .read_to_end(&mut data)
-        .map_err(|e| format!("读取数据失败: {}", e))?;
+    // 按 tarball 文件名保存在目标目录旁边，换了版本时不会续传到旧文件上
+    let file_name = tarball_url.rsplit('/').next().filter(|n| !n.is_empty()).unwrap_or(DEFAULT_ARCHIVE_NAME);
+    let archive_path = extract_dir.with_file_name(file_name);
+    http::download_resumable(
+        tarball_url,
+        &archive_path,
+        Duration::from_secs(DOWNLOAD_TIMEOUT_SECS),
+        print_download_progress,
+    )
+    .inspect_err(|_| println!())?;
+    println!();
+    let data = fs::read(&archive_path).map_err(|e| format!("读取下载的文件失败: {}", e));
+    let _ = fs::remove_file(&archive_path);
+    let data = data?;
     
     println!("下载完成，大小: {} KB", data.len() / 1024);
     
-    fs::create_dir_all(extract_dir)
-        .map_err(|e| format!("创建目录失败: {}", e))?;
+    match checksum {
+        Some(checksum) => {
+            checksum.verify(&data)?;
+            println!("校验通过");
+        }
+        None => verbose!("跳过校验: 没有可用的校验值或已指定 --no-verify"),
+    }
     
-    let temp_file = extract_dir.join("_temp_download.tgz");
-    fs::write(&temp_file, &data)
-        .map_err(|e| format!("保存临时文件失败: {}", e))?;
+    extract_local_tarball(&data, extract_dir, component, verify)
+}
+
+/// 把压缩包内容解压并整体替换 `extract_dir`，支持 tar.gz 和 zip；
+/// 给定 `component` 时先确认包内有该组件的关键文件，替换后 `verify` 失败时恢复原有文件
+pub fn extract_local_tarball(
+    data: &[u8],
+    extract_dir: &Path,
+    component: Option<&str>,
+    verify: &dyn Fn() -> Result<(), String>,
+) -> Result<(), String> {
+    // 临时文件放在目标目录旁边，避免被一起复制进新目录
+    let temp_extract = sibling_path(extract_dir, "download");
+    let _ = fs::remove_dir_all(&temp_extract);
+    fs::create_dir_all(&temp_extract)
+        .map_err(|e| format!("创建临时解压目录失败: {}", e))?;
     
     println!("解压中...");
     
-    let file = File::open(&temp_file)
-        .map_err(|e| format!("打开临时文件失败: {}", e))?;
-    let gz = flate2::read::GzDecoder::new(BufReader::new(file));
-    let mut archive = tar::Archive::new(gz);
-    
-    let temp_extract = extract_dir.join("_temp_extract");
-    fs::create_dir_all(&temp_extract)
-        .map_err(|e| format!("创建临时解压目录失败: {}", e))?;
+    let result = unpack_archive(data, &temp_extract)
+        .map_err(|e| format!("解压失败: {}", e))
+        .and_then(|()| {
+            let package_dir = package_root(&temp_extract)?;
+            verbose!("包目录: {}", package_dir.display());
+            if let Some(component) = component {
+                validate_package(&package_dir, component)
+                    .map_err(|e| format!("{}，可能已损坏或不是对应的组件，已保留原有文件", e))?;
+            }
+            install_atomically(&package_dir, extract_dir, verify)
+        });
+    let _ = fs::remove_dir_all(&temp_extract);
+    result?;
     
-    archive.unpack(&temp_extract)
-        .map_err(|e| format!("解压失败: {}", e))?;
-    
-    let package_dir = temp_extract.join("package");
-    if package_dir.exists() {
-        for entry in fs::read_dir(&package_dir).map_err(|e| format!("读取目录失败: {}", e))? {
-            let entry = entry.map_err(|e| format!("读取条目失败: {}", e))?;
-            let src = entry.path();
-            let dst = extract_dir.join(entry.file_name());
-            
-            if dst.exists() {
-                if dst.is_dir() {
-                    let _ = fs::remove_dir_all(&dst);
-                } else {
-                    let _ = fs::remove_file(&dst);
-                }
+    println!("解压完成");
+    Ok(())
+}
+
+/// 根据文件头判断格式：zip 以 `PK\x03\x04` 开头，其余按 tar.gz 处理。
+/// 镜像改写后的地址不一定带有扩展名，因此不依赖 URL
+fn unpack_archive(data: &[u8], dest: &Path) -> Result<(), String> {
+    if data.starts_with(b"PK\x03\x04") {
+        verbose!("压缩包格式: zip");
+        let mut archive = zip::ZipArchive::new(io::Cursor::new(data)).map_err(|e| e.to_string())?;
+        archive.extract(dest).map_err(|e| e.to_string())
+    } else {
+        verbose!("压缩包格式: tar.gz");
+        let gz = flate2::read::GzDecoder::new(data);
+        tar::Archive::new(gz).unpack(dest).map_err(|e| e.to_string())
+    }
+}
+
+/// 解压后的包目录：npm 的 tarball 顶层为 `package/`，重新打包的文件可能使用其他名称，
+/// 顶层只有一个目录时直接使用，有多个条目时只接受其中的 `package/`；
+/// zip 包的文件可能直接放在顶层，此时使用解压目录本身
+fn package_root(extracted: &Path) -> Result<PathBuf, String> {
+    let entries: Vec<PathBuf> = fs::read_dir(extracted)
+        .map_err(|e| format!("读取解压目录失败: {}", e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    match entries.as_slice() {
+        [] => Err("压缩包为空".to_string()),
+        [only] if only.is_dir() => Ok(only.clone()),
+        _ if entries.iter().all(|p| p.is_file()) => Ok(extracted.to_path_buf()),
+        _ => {
+            let package = extracted.join("package");
+            if package.is_dir() {
+                return Ok(package);
             }
-            
-            fs::rename(&src, &dst)
-                .or_else(|_| copy_recursive(&src, &dst))
-                .map_err(|e| format!("移动文件失败: {}", e))?;
+            let names: Vec<String> = entries
+                .iter()
+                .filter_map(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+                .collect();
+            Err(format!(
+                "压缩包顶层应只有一个目录，实际为: {}，已保留原有文件",
+                names.join(", ")
+            ))
         }
     }
+}
+
+/// 与 `dir` 同级、名为 `<dir>.<suffix>` 的路径
+fn sibling_path(dir: &Path, suffix: &str) -> PathBuf {
+    let name = dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    dir.with_file_name(format!("{}.{}", name, suffix))
+}
+
+/// 整体替换 `target` 目录：暂存目录中只放包内文件，`target` 里包中没有的项（data/、配置等用户文件）
+/// 改名移入暂存目录而不是复制，再把 `target` 改名为 `.old`、暂存目录改名为 `target`；
+/// `verify` 确认新版本可用后删除 `.old`，任一步失败时把用户文件移回并恢复原目录
+fn install_atomically(
+    package_dir: &Path,
+    target: &Path,
+    verify: &dyn Fn() -> Result<(), String>,
+) -> Result<(), String> {
+    let staging = sibling_path(target, "staging");
+    let old = sibling_path(target, "old");
+    
+    // 上次替换在两次改名之间中断时，先恢复原目录
+    if !target.exists() && old.exists() {
+        fs::rename(&old, target).map_err(|e| format!("恢复上次未完成的安装失败: {}", e))?;
+    }
+    let _ = fs::remove_dir_all(&staging);
+    let _ = fs::remove_dir_all(&old);
+    
+    let prepared = fs::create_dir_all(&staging)
+        .map_err(|e| format!("创建暂存目录失败: {}", e))
+        .and_then(|()| {
+            if package_dir.exists() {
+                move_entries(package_dir, &staging)
+            } else {
+                Ok(())
+            }
+        })
+        .and_then(|()| {
+            if target.exists() {
+                carry_user_entries(target, &staging)
+            } else {
+                Ok(Vec::new())
+            }
+        });
+    let carried = match prepared {
+        Ok(carried) => carried,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+    };
     
-    let _ = fs::remove_dir_all(&temp_extract);
-    let _ = fs::remove_file(&temp_file);
-    
-    println!("解压完成");
+    if target.exists() {
+        if let Err(e) = fs::rename(target, &old) {
+            let _ = rename_entries(&carried, &staging, target);
+            let _ = fs::remove_dir_all(&staging);
+            return Err(format!("替换 {} 失败，文件可能正在使用: {}", target.display(), e));
+        }
+    }
+    if let Err(e) = fs::rename(&staging, target) {
+        let _ = fs::rename(&old, target);
+        let _ = rename_entries(&carried, &staging, target);
+        let _ = fs::remove_dir_all(&staging);
+        return Err(format!("替换 {} 失败，已恢复原有文件: {}", target.display(), e));
+    }
+    if let Err(e) = verify() {
+        if !old.exists() {
+            return Err(e);
+        }
+        let restored = rename_entries(&carried, target, &old)
+            .and_then(|()| fs::remove_dir_all(target))
+            .and_then(|()| fs::rename(&old, target));
+        return match restored {
+            Ok(()) => Err(format!("{}，已恢复原有文件", e)),
+            Err(restore_err) => Err(format!(
+                "{}，恢复原有文件失败: {}，备份位于 {}",
+                e,
+                restore_err,
+                old.display()
+            )),
+        };
+    }
+    let _ = fs::remove_dir_all(&old);
     Ok(())
 }
 
-fn copy_recursive(src: &Path, dst: &Path) -> io::Result<()> {
-    if src.is_dir() {
-        fs::create_dir_all(dst)?;
-        for entry in fs::read_dir(src)? {
-            let entry = entry?;
-            copy_recursive(&entry.path(), &dst.join(entry.file_name()))?;
+/// 把 `target` 中包内没有的项改名移入 `staging`，返回移动过的名称；中途失败时把已移动的项移回
+fn carry_user_entries(target: &Path, staging: &Path) -> Result<Vec<OsString>, String> {
+    let mut carried = Vec::new();
+    let entries = fs::read_dir(target).map_err(|e| format!("读取 {} 失败: {}", target.display(), e))?;
+    for entry in entries {
+        let name = match entry {
+            Ok(entry) => entry.file_name(),
+            Err(e) => {
+                let _ = rename_entries(&carried, staging, target);
+                return Err(format!("读取条目失败: {}", e));
+            }
+        };
+        if staging.join(&name).exists() {
+            continue;
+        }
+        if let Err(e) = fs::rename(target.join(&name), staging.join(&name)) {
+            let _ = rename_entries(&carried, staging, target);
+            return Err(format!("移动 {} 失败，文件可能正在使用: {}", target.join(&name).display(), e));
+        }
+        carried.push(name);
+    }
+    Ok(carried)
+}
+
+/// 把 `from` 下的 `names` 逐项改名移到 `to`，全部尝试后返回第一个错误
+fn rename_entries(names: &[OsString], from: &Path, to: &Path) -> io::Result<()> {
+    let mut result = Ok(());
+    for name in names {
+        if let Err(e) = fs::rename(from.join(name), to.join(name)) {
+            if result.is_ok() {
+                result = Err(e);
+            }
+        }
+    }
+    result
+}
+
+/// 更新后确认组件可用：版本与预期一致，pmhq 还需能在本机运行
+fn verify_install(exe_dir: &Path, component: &str, expected: Option<&str>) -> Result<(), String> {
+    let installed = get_local_version(exe_dir, component);
+    if let Some(expected) = expected.filter(|v| *v != installed) {
+        return Err(format!("安装后的版本为 {}，应为 {}", installed, expected));
+    }
+    if component == "pmhq" {
+        verify_pmhq_runs(exe_dir)?;
+    }
+    verbose!("{} {} 校验通过", component, installed);
+    Ok(())
+}
+
+/// 运行新的 `pmhq --version`：无法启动或以非零状态码退出时视为损坏；
+/// 不支持该参数而一直运行时，超时后结束它及其启动的子进程并视为可以启动
+fn verify_pmhq_runs(exe_dir: &Path) -> Result<(), String> {
+    let pmhq = crate::find_pmhq_exe(exe_dir).ok_or("未找到 pmhq 可执行文件")?;
+    if let Ok(Some(arch)) = crate::doctor::binary_arch(&pmhq) {
+        if arch != ARCH {
+            return Err(format!("pmhq 是 {} 架构的程序，与当前系统 {} 不符", arch, ARCH));
+        }
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(metadata) = fs::metadata(&pmhq) {
+            let mut perms = metadata.permissions();
+            if perms.mode() & 0o111 == 0 {
+                perms.set_mode(perms.mode() | 0o755);
+                let _ = fs::set_permissions(&pmhq, perms);
+            }
+        }
+    }
+
+    // 在单独的进程组中运行，超时后连同它可能启动的 QQ / node 一起结束
+    let mut child = Command::new(&pmhq)
+        .arg("--version")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .group_spawn()
+        .map_err(|e| format!("无法运行 {}: {}", pmhq.display(), e))?;
+    let deadline = std::time::Instant::now() + VERIFY_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => {
+                let mut output = String::new();
+                if let Some(mut stdout) = child.inner().stdout.take() {
+                    let _ = stdout.read_to_string(&mut output);
+                }
+                verbose!("pmhq --version: {}", output.trim());
+                return Ok(());
+            }
+            Ok(Some(status)) => {
+                return Err(format!("pmhq --version 运行失败，状态码: {:?}", status.code()));
+            }
+            Ok(None) if std::time::Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                verbose!("pmhq --version 在 {} 秒内未退出，视为可以启动", VERIFY_TIMEOUT.as_secs());
+                return Ok(());
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(100)),
+            Err(e) => return Err(format!("等待 pmhq 失败: {}", e)),
         }
+    }
+}
+
+/// 在同一行刷新下载进度；没有 content-length 时只显示已下载的大小
+pub fn print_download_progress(downloaded: u64, total: u64) {
+    let mb = |bytes: u64| bytes as f64 / 1024.0 / 1024.0;
+    if total > 0 {
+        print!(
+            "\r下载进度: {:.1} MB / {:.1} MB ({:.0}%)",
+            mb(downloaded),
+            mb(total),
+            downloaded as f64 / total as f64 * 100.0
+        );
     } else {
-        fs::copy(src, dst)?;
+        print!("\r已下载: {:.1} MB", mb(downloaded));
+    }
+    let _ = io::stdout().flush();
+}
+
+/// 把 `src_dir` 下的每一项移动到 `dst_dir`，覆盖同名文件或目录
+fn move_entries(src_dir: &Path, dst_dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dst_dir).map_err(|e| format!("创建目录失败: {}", e))?;
+    for entry in fs::read_dir(src_dir).map_err(|e| format!("读取目录失败: {}", e))? {
+        let entry = entry.map_err(|e| format!("读取条目失败: {}", e))?;
+        let src = entry.path();
+        let dst = dst_dir.join(entry.file_name());
+        
+        if dst.exists() {
+            if dst.is_dir() {
+                let _ = fs::remove_dir_all(&dst);
+            } else {
+                let _ = fs::remove_file(&dst);
+            }
+        }
+        
+        fs::rename(&src, &dst)
+            .or_else(|_| fsutil::copy_recursive(&src, &dst))
+            .map_err(|e| format!("移动文件失败: {}", e))?;
     }
     Ok(())
 }
@@ -389,31 +1077,132 @@ pub fn prompt_yes_no(prompt: &str) -> bool {
     false
 }
 
-pub fn run_update(exe_dir: &Path) {
+#[derive(Debug, Default)]
+pub struct UpdateOptions {
+    /// 无论版本是否最新都重新下载 PMHQ 和 LLBot
+    pub reinstall: bool,
+    /// 只下载到 pending/ 目录，下次启动时再替换
+    pub background: bool,
+    /// 跳过下载内容的校验和检查，仅用于排查问题
+    pub no_verify: bool,
+    /// `--update=<组件>` 指定时只更新该组件，值为更新表格中的组件名
+    pub only: Option<&'static str>,
+}
+
+/// 解析 `--update=` 的取值：cli、pmhq 或 llbot
+pub fn parse_component(value: &str) -> Option<&'static str> {
+    match value.to_ascii_lowercase().as_str() {
+        "cli" => Some("LLBot CLI"),
+        "pmhq" => Some("PMHQ"),
+        "llbot" => Some("LLBot"),
+        _ => None,
+    }
+}
+
+/// 获取 LLBot 的包信息以确认 registry 可访问，返回其最新版本
+pub fn check_registry(exe_dir: &Path) -> Result<String, String> {
+    set_mirror_cache_dir(exe_dir);
+    let packages = ComponentPackages::for_current_platform();
+    fetch_package_info(&packages.llbot_package).map(|info| info.version)
+}
+
+/// `--check-update` 发现可用更新时的退出码
+pub const EXIT_UPDATE_AVAILABLE: i32 = 10;
+
+/// 只检查并输出更新表格，不提示、不下载；返回进程退出码
+pub fn run_check_update(exe_dir: &Path) -> i32 {
+    set_mirror_cache_dir(exe_dir);
+    let packages = ComponentPackages::for_current_platform();
+    let pmhq_version = get_local_version(exe_dir, "pmhq");
+    let llbot_version = get_local_version(exe_dir, "llbot");
+    
+    let updates = [
+        check_update("LLBot CLI", &packages.cli_package, cli_version()),
+        check_update("PMHQ", &packages.pmhq_package, &pmhq_version),
+        check_update("LLBot", &packages.llbot_package, &llbot_version),
+    ];
+    print_update_table(&updates.iter().collect::<Vec<_>>());
+    
+    if updates.iter().any(|u| u.has_update) {
+        EXIT_UPDATE_AVAILABLE
+    } else {
+        0
+    }
+}
+
+/// `--list-versions`：以 JSON 输出各组件的当前版本和最新版本，供脚本使用；
+/// 未安装或无法访问 registry 时对应的版本为 null
+pub fn run_list_versions(exe_dir: &Path) {
+    set_mirror_cache_dir(exe_dir);
+    let packages = ComponentPackages::for_current_platform();
+    let pmhq_version = get_local_version(exe_dir, "pmhq");
+    let llbot_version = get_local_version(exe_dir, "llbot");
+    let known = |v: &str| (v != "未知" && v != "未安装").then(|| v.to_string());
+    let entry = |info: UpdateInfo| {
+        serde_json::json!({
+            "current": known(&info.current_version),
+            "latest": known(&info.latest_version),
+            "has_update": info.has_update,
+        })
+    };
+
+    let versions = serde_json::json!({
+        "cli": entry(check_update("LLBot CLI", &packages.cli_package, cli_version())),
+        "pmhq": entry(check_update("PMHQ", &packages.pmhq_package, &pmhq_version)),
+        "llbot": entry(check_update("LLBot", &packages.llbot_package, &llbot_version)),
+    });
+    println!("{}", serde_json::to_string_pretty(&versions).unwrap_or_default());
+}
+
+pub fn run_update(exe_dir: &Path, opts: UpdateOptions) {
+    set_mirror_cache_dir(exe_dir);
     println!("LLBot 更新检查");
     println!("===============");
     println!();
     
     let packages = ComponentPackages::for_current_platform();
-    let cli_version = env!("CARGO_PKG_VERSION");
+    let cli_version = cli_version();
     let pmhq_version = get_local_version(exe_dir, "pmhq");
     let llbot_version = get_local_version(exe_dir, "llbot");
     
     println!("检查更新中...");
     println!();
     
-    let cli_update = check_update("LLBot CLI", &packages.cli_package, cli_version);
-    let pmhq_update = check_update("PMHQ", &packages.pmhq_package, &pmhq_version);
-    let llbot_update = check_update("LLBot", &packages.llbot_package, &llbot_version);
+    let check_all = || {
+        (
+            check_update("LLBot CLI", &packages.cli_package, cli_version),
+            check_update("PMHQ", &packages.pmhq_package, &pmhq_version),
+            check_update("LLBot", &packages.llbot_package, &llbot_version),
+        )
+    };
+    let (mut cli_update, mut pmhq_update, mut llbot_update) = check_all();
+    let all_failed = [&cli_update, &pmhq_update, &llbot_update]
+        .iter()
+        .all(|u| u.latest_version == "未知");
+    if all_failed && prompt_custom_registry(&packages.llbot_package) {
+        println!();
+        (cli_update, pmhq_update, llbot_update) = check_all();
+    }
+
+    if opts.reinstall {
+        force_reinstall(&mut pmhq_update, &packages.pmhq_package);
+        force_reinstall(&mut llbot_update, &packages.llbot_package);
+    }
     
-    println!("组件          当前版本        最新版本        状态");
-    println!("----          --------        --------        ----");
-    print_update_row(&cli_update);
-    print_update_row(&pmhq_update);
-    print_update_row(&llbot_update);
-    println!();
+    if opts.no_verify {
+        println!("{}", color::yellow("已指定 --no-verify，将跳过下载校验"));
+        for update in [&mut cli_update, &mut pmhq_update, &mut llbot_update] {
+            update.checksum = None;
+        }
+    }
     
-    let updates: Vec<&UpdateInfo> = [&cli_update, &pmhq_update, &llbot_update]
+    let selected: Vec<&UpdateInfo> = [&cli_update, &pmhq_update, &llbot_update]
+        .into_iter()
+        .filter(|u| opts.only.is_none_or(|only| u.name == only))
+        .collect();
+    print_update_table(&selected);
+    
+    let updates: Vec<&UpdateInfo> = selected
         .into_iter()
         .filter(|u| u.has_update && u.tarball_url.is_some())
         .collect();
@@ -424,29 +1213,16 @@ pub fn run_update(exe_dir: &Path) {
     }
     
     println!("发现 {} 个可用更新", updates.len());
+    if let Err(e) = check_disk_space(exe_dir, &updates) {
+        eprintln!("{}", color::red(&e));
+        return;
+    }
     
-    #[cfg(target_os = "windows")]
-    {
-        let running = check_running_processes();
-        if !running.is_empty() {
-            println!();
-            println!("检测到以下进程正在运行:");
-            for (name, pid) in &running {
-                println!("  - {} (PID: {})", name, pid);
-            }
-            println!();
-            
-            if prompt_yes_no("是否关闭这些进程?") {
-                for (name, pid) in &running {
-                    print!("正在关闭 {}...", name);
-                    if kill_process(*pid) {
-                        println!(" 完成");
-                    } else {
-                        println!(" 失败");
-                    }
-                }
-                println!();
-            }
+    // 后台模式不替换正在使用的文件，无需关闭进程
+    if !opts.background {
+        if let Err(e) = stop_running_processes() {
+            eprintln!("{}", color::red(&e));
+            return;
         }
     }
     
@@ -456,37 +1232,241 @@ pub fn run_update(exe_dir: &Path) {
     }
     
     println!();
+    install_updates(exe_dir, &updates, &opts);
+}
+
+/// `--install-version=<组件>@<版本>`：安装指定版本，即使比当前版本旧
+pub fn run_install_version(exe_dir: &Path, spec: &str, opts: UpdateOptions) -> Result<(), String> {
+    set_mirror_cache_dir(exe_dir);
+    let (component, version) = spec
+        .split_once('@')
+        .filter(|(_, v)| !v.is_empty())
+        .ok_or_else(|| format!("格式应为 <组件>@<版本>，例如 llbot@1.2.3: {}", spec))?;
+    let name = parse_component(component)
+        .ok_or_else(|| format!("组件只支持 cli、pmhq 或 llbot: {}", component))?;
+    let version = version.trim_start_matches('v');
+    
+    let packages = ComponentPackages::for_current_platform();
+    let (package_name, current_version) = match name {
+        "LLBot CLI" => (packages.cli_package, cli_version().to_string()),
+        "PMHQ" => (packages.pmhq_package, get_local_version(exe_dir, "pmhq")),
+        _ => (packages.llbot_package, get_local_version(exe_dir, "llbot")),
+    };
+    
+    let registry = get_best_download_registry(&package_name, version);
+    if !check_version_exists(&package_name, version, &registry) {
+        return Err(format!("{} 不存在版本 {}", package_name, version));
+    }
+    
+    let encoded_name = package_name.replace("/", "%2F");
+    let mut checksum = None;
+    let mut unpacked_size = None;
+    if let Some(packument) = fetch_packument(&registry, &encoded_name) {
+        cache_tarballs(&package_name, &registry, &packument);
+        let dist = packument.versions.get(version).and_then(|v| v.dist.as_ref());
+        checksum = dist.and_then(Checksum::from_dist);
+        unpacked_size = dist.and_then(|d| d.unpacked_size);
+    }
+    if opts.no_verify {
+        println!("{}", color::yellow("已指定 --no-verify，将跳过下载校验"));
+        checksum = None;
+    }
+    
+    let update = UpdateInfo {
+        name: name.to_string(),
+        current_version: current_version.clone(),
+        latest_version: version.to_string(),
+        has_update: true,
+        tarball_url: Some(tarball_url_on(&package_name, version, &registry)),
+        checksum,
+        unpacked_size,
+    };
+    print_update_table(&[&update]);
+    check_disk_space(exe_dir, &[&update])?;
+    
+    if compare_versions(version, &current_version) {
+        println!(
+            "{}",
+            color::yellow(&format!("注意: 将把 {} 从 {} 降级到 {}", name, current_version, version))
+        );
+    }
+    
+    if !opts.background {
+        stop_running_processes()?;
+    }
+    if !prompt_yes_no(&format!("是否安装 {} {}?", name, version)) {
+        println!("安装已取消");
+        return Ok(());
+    }
     
+    println!();
+    install_updates(exe_dir, &[&update], &opts);
+    Ok(())
+}
+
+/// `--update-from=<路径>[:组件]`：不访问网络，直接安装本地的压缩包；
+/// 未指定组件时根据文件名判断（pmhq-dist-*、llonebot-dist-*）
+pub fn run_update_from(exe_dir: &Path, spec: &str) -> Result<(), String> {
+    // Windows 路径本身含有冒号，只有冒号后是组件名时才拆分
+    let (path, component) = match spec.rsplit_once(':') {
+        Some((path, component)) if parse_component(component).is_some() => {
+            (Path::new(path), parse_component(component))
+        }
+        _ => (Path::new(spec), None),
+    };
+    let file_name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let name = component
+        .or_else(|| {
+            if file_name.starts_with("pmhq") {
+                Some("PMHQ")
+            } else if file_name.starts_with("llonebot") {
+                Some("LLBot")
+            } else {
+                None
+            }
+        })
+        .ok_or_else(|| {
+            format!("无法根据文件名判断组件，请以 {}:pmhq 或 {}:llbot 的形式指定", spec, spec)
+        })?;
+    let component = component_key(name)
+        .ok_or_else(|| "LLBot CLI 不支持从本地文件更新，请直接替换可执行文件".to_string())?;
+
+    let data = fs::read(path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+    let target_dir = exe_dir.join("bin").join(component);
+    println!("从本地文件安装 {}: {}", name, path.display());
+    println!("当前版本: {}", get_local_version(exe_dir, component));
+
+    stop_running_processes()?;
+    if !prompt_yes_no(&format!("是否用该文件替换 {}?", target_dir.display())) {
+        println!("安装已取消");
+        return Ok(());
+    }
+
+    extract_local_tarball(&data, &target_dir, Some(component), &|| {
+        verify_install(exe_dir, component, None)
+    })?;
+    println!("{} 已更新到 {}", name, get_local_version(exe_dir, component));
+    Ok(())
+}
+
+/// 下载前估算所需空间：压缩包、解压出的临时目录和暂存目录各约一份解压后大小；
+/// 现有的 data/ 等文件改名移入新目录，不占用额外空间。
+/// 元数据未提供大小或无法获取可用空间时跳过检查
+fn check_disk_space(exe_dir: &Path, updates: &[&UpdateInfo]) -> Result<(), String> {
+    let required: u64 = updates
+        .iter()
+        .filter_map(|u| u.unpacked_size)
+        .map(|size| size.saturating_mul(DISK_SPACE_FACTOR))
+        .sum();
+    if required == 0 {
+        verbose!("元数据中没有解压后大小，跳过磁盘空间检查");
+        return Ok(());
+    }
+    let Some(available) = available_space(exe_dir) else {
+        verbose!("无法获取 {} 所在磁盘的可用空间，跳过检查", exe_dir.display());
+        return Ok(());
+    };
+    let mb = |bytes: u64| bytes as f64 / 1024.0 / 1024.0;
+    verbose!("更新约需 {:.1} MB，可用 {:.1} MB", mb(required), mb(available));
+    if available < required {
+        return Err(format!(
+            "磁盘空间不足: 更新约需 {:.1} MB，{} 所在磁盘只剩 {:.1} MB，请清理后重试",
+            mb(required),
+            exe_dir.display(),
+            mb(available)
+        ));
+    }
+    Ok(())
+}
+
+/// `path` 所在文件系统中当前用户可用的字节数
+#[cfg(not(target_os = "windows"))]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path 是以 NUL 结尾的路径，stat 由 statvfs 填充
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// `path` 所在磁盘中当前用户可用的字节数
+#[cfg(target_os = "windows")]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory: *const u16,
+            free_to_caller: *mut u64,
+            total: *mut u64,
+            total_free: *mut u64,
+        ) -> i32;
+    }
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut available = 0u64;
+    // SAFETY: wide 以 0 结尾，不需要的输出参数传空指针
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            wide.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    (ok != 0).then_some(available)
+}
+
+/// 下载并安装 `updates` 中的组件，LLBot CLI 最后自更新
+fn install_updates(exe_dir: &Path, updates: &[&UpdateInfo], opts: &UpdateOptions) {
     let mut need_self_update = false;
     
-    for update in &updates {
+    for update in updates {
         if update.name == "LLBot CLI" {
             need_self_update = true;
             continue;
         }
         
-        let target_dir = match update.name.as_str() {
-            "PMHQ" => exe_dir.join("bin/pmhq"),
-            "LLBot" => exe_dir.join("bin/llbot"),
-            _ => continue,
+        let Some(component) = component_key(&update.name) else {
+            continue;
         };
+        let target_dir = exe_dir.join("bin").join(component);
         
         println!("更新 {}...", update.name);
         
         if let Some(ref url) = update.tarball_url {
-            match download_and_extract(url, &target_dir) {
-                Ok(()) => println!("{} 更新成功!", update.name),
-                Err(e) => eprintln!("{} 更新失败: {}", update.name, e),
+            if opts.background {
+                match stage_update(url, exe_dir, component, update) {
+                    Ok(()) => println!("{} 已下载，将在下次启动时应用", update.name),
+                    Err(e) => eprintln!("{} 下载失败: {}", update.name, e),
+                }
+            } else {
+                let verify = || verify_install(exe_dir, component, Some(&update.latest_version));
+                let installed = download_and_extract(
+                    url,
+                    &target_dir,
+                    update.checksum.as_ref(),
+                    Some(component),
+                    &verify,
+                );
+                match installed {
+                    Ok(()) => println!("{} 更新成功!", update.name),
+                    Err(e) => eprintln!("{} 更新失败: {}", update.name, e),
+                }
             }
         }
         println!();
     }
     
-    if need_self_update {
+    if need_self_update && opts.background {
+        println!("LLBot CLI 需要在前台更新，请稍后运行 --update");
+    } else if need_self_update {
         if let Some(cli_update) = updates.iter().find(|u| u.name == "LLBot CLI") {
             println!("更新 LLBot CLI...");
             if let Some(ref url) = cli_update.tarball_url {
-                match self_update(url, exe_dir) {
+                match self_update(url, cli_update.checksum.as_ref(), exe_dir) {
                     Ok(()) => return,
                     Err(e) => eprintln!("LLBot CLI 更新失败: {}", e),
                 }
@@ -497,17 +1477,251 @@ pub fn run_update(exe_dir: &Path) {
     println!("更新完成!");
 }
 
-fn print_update_row(info: &UpdateInfo) {
-    let status = if info.has_update { "有更新" } else { "最新" };
-    println!(
-        "{:<12}  {:<14}  {:<14}  {}",
-        info.name, info.current_version, info.latest_version, status
-    );
+/// 正在运行的 llbot / pmhq（含运行 llbot.js 的 node），QQ 不使用 bin/ 下的文件，不在其中
+fn running_bot_processes() -> Vec<(String, u32)> {
+    check_running_processes()
+        .into_iter()
+        .filter(|(name, _)| !name.to_lowercase().starts_with("qq"))
+        .collect()
+}
+
+/// 替换组件文件前确认 llbot 和 pmhq 都已停止：正在运行时询问是否关闭，仍未停止则拒绝安装。
+/// Windows 上运行中的文件被锁住，其他平台上替换运行中的组件也会让它读到新旧混合的文件
+fn stop_running_processes() -> Result<(), String> {
+    let running = running_bot_processes();
+    if running.is_empty() {
+        return Ok(());
+    }
+    println!();
+    println!("检测到以下进程正在运行:");
+    for (name, pid) in &running {
+        println!("  - {} (PID: {})", name, pid);
+    }
+    println!();
+    
+    if prompt_yes_no("是否关闭这些进程?") {
+        for (name, pid) in &running {
+            print!("正在关闭 {}...", name);
+            if kill_process(*pid) {
+                println!(" 完成");
+            } else {
+                println!(" 失败");
+            }
+        }
+        println!();
+        // 给被结束的进程一点时间退出
+        thread::sleep(Duration::from_millis(500));
+    }
+    
+    let remaining = running_bot_processes();
+    if remaining.is_empty() {
+        return Ok(());
+    }
+    let list: Vec<String> = remaining
+        .iter()
+        .map(|(name, pid)| format!("{} (PID: {})", name, pid))
+        .collect();
+    Err(format!("llbot 或 pmhq 仍在运行，无法替换组件: {}，请先停止后重试", list.join(", ")))
+}
+
+/// 组件名对应的 bin/ 子目录名，也用作 pending/ 下的暂存目录名
+fn component_key(name: &str) -> Option<&'static str> {
+    match name {
+        "PMHQ" => Some("pmhq"),
+        "LLBot" => Some("llbot"),
+        _ => None,
+    }
+}
+
+/// 确认解压出的组件包含关键文件：pmhq 需要 pmhq 可执行文件（可带平台后缀），llbot 需要 llbot.js
+fn validate_package(package_dir: &Path, component: &str) -> Result<(), String> {
+    let found = match component {
+        "pmhq" => fs::read_dir(package_dir)
+            .map(|entries| {
+                entries.flatten().any(|e| {
+                    e.path().is_file() && e.file_name().to_string_lossy().starts_with("pmhq")
+                })
+            })
+            .unwrap_or(false),
+        _ => package_dir.join("llbot.js").is_file(),
+    };
+    if found {
+        Ok(())
+    } else if component == "pmhq" {
+        Err("安装包中缺少 pmhq 可执行文件".to_string())
+    } else {
+        Err("安装包中缺少 llbot.js".to_string())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingManifest {
+    version: String,
+    /// 相对暂存目录的路径 -> SHA-256
+    files: BTreeMap<String, String>,
+}
+
+/// 下载并解压到 pending/<component>，最后写入校验清单；清单存在即表示暂存完整
+fn stage_update(
+    url: &str,
+    exe_dir: &Path,
+    component: &str,
+    update: &UpdateInfo,
+) -> Result<(), String> {
+    let pending_dir = exe_dir.join(PENDING_DIR);
+    let stage_dir = pending_dir.join(component);
+    let manifest_path = pending_dir.join(format!("{}.json", component));
+    
+    let _ = fs::remove_file(&manifest_path);
+    if stage_dir.exists() {
+        fs::remove_dir_all(&stage_dir).map_err(|e| format!("清理暂存目录失败: {}", e))?;
+    }
+    
+    // 暂存目录不是最终位置，应用时再校验
+    download_and_extract(url, &stage_dir, update.checksum.as_ref(), Some(component), &|| Ok(()))?;
+    
+    let mut files = BTreeMap::new();
+    hash_tree(&stage_dir, &stage_dir, &mut files).map_err(|e| format!("计算校验值失败: {}", e))?;
+    let manifest = PendingManifest {
+        version: update.latest_version.clone(),
+        files,
+    };
+    let json = serde_json::to_string_pretty(&manifest).map_err(|e| format!("序列化失败: {}", e))?;
+    fs::write(&manifest_path, json).map_err(|e| format!("写入校验清单失败: {}", e))?;
+    Ok(())
+}
+
+fn hash_tree(root: &Path, dir: &Path, files: &mut BTreeMap<String, String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            hash_tree(root, &path, files)?;
+        } else {
+            let rel = path.strip_prefix(root).unwrap_or(&path);
+            let rel = rel.to_string_lossy().replace('\\', "/");
+            files.insert(rel, sha256_file(&path)?);
+        }
+    }
+    Ok(())
 }
 
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn verify_staged(stage_dir: &Path, manifest_path: &Path) -> Result<String, String> {
+    let content = fs::read_to_string(manifest_path).map_err(|_| "缺少校验清单".to_string())?;
+    let manifest: PendingManifest =
+        serde_json::from_str(&content).map_err(|e| format!("校验清单无效: {}", e))?;
+    
+    for (rel, expected) in &manifest.files {
+        let actual = sha256_file(&stage_dir.join(rel)).map_err(|_| format!("缺少文件 {}", rel))?;
+        if &actual != expected {
+            return Err(format!("文件 {} 校验失败", rel));
+        }
+    }
+    Ok(manifest.version)
+}
+
+/// 启动时应用 `--update --background` 暂存的更新；校验失败则丢弃暂存，继续使用当前版本
+pub fn apply_pending_updates(exe_dir: &Path) {
+    let pending_dir = exe_dir.join(PENDING_DIR);
+    if !pending_dir.is_dir() {
+        return;
+    }
+    // 另一个实例仍在使用这些组件时保留暂存的更新，下次启动再应用
+    let running = running_bot_processes();
+    if !running.is_empty() {
+        let list: Vec<String> = running
+            .iter()
+            .map(|(name, pid)| format!("{} (PID: {})", name, pid))
+            .collect();
+        warning!("llbot 或 pmhq 仍在运行 ({})，暂不应用已下载的更新", list.join(", "));
+        return;
+    }
+    
+    for component in ["pmhq", "llbot"] {
+        let stage_dir = pending_dir.join(component);
+        let manifest_path = pending_dir.join(format!("{}.json", component));
+        if !stage_dir.exists() && !manifest_path.exists() {
+            continue;
+        }
+        
+        match verify_staged(&stage_dir, &manifest_path) {
+            Ok(version) => {
+                println!("正在应用已下载的 {} {} 更新...", component, version);
+                let verify = || verify_install(exe_dir, component, Some(&version));
+                match install_atomically(&stage_dir, &exe_dir.join("bin").join(component), &verify) {
+                    Ok(()) => println!("{} 更新完成", component),
+                    Err(e) => warning!("应用 {} 更新失败: {}", component, e),
+                }
+            }
+            Err(e) => warning!("暂存的 {} 更新不完整，继续使用当前版本: {}", component, e),
+        }
+        
+        let _ = fs::remove_dir_all(&stage_dir);
+        let _ = fs::remove_file(&manifest_path);
+    }
+    let _ = fs::remove_dir(&pending_dir);
+}
+
+fn force_reinstall(info: &mut UpdateInfo, package_name: &str) {
+    if info.tarball_url.is_none() && info.latest_version != "未知" {
+        info.tarball_url = Some(get_tarball_url(package_name, &info.latest_version));
+    }
+    info.has_update = info.tarball_url.is_some();
+}
+
+fn print_update_table(rows: &[&UpdateInfo]) {
+    const HEADERS: [&str; 4] = ["组件", "当前版本", "最新版本", "状态"];
+    let cells: Vec<[String; 3]> = rows
+        .iter()
+        .map(|r| [r.name.clone(), r.current_version.clone(), r.latest_version.clone()])
+        .collect();
+    // 状态列在最后，不需要补齐
+    let mut widths = [0; 3];
+    for (i, width) in widths.iter_mut().enumerate() {
+        *width = cells
+            .iter()
+            .map(|c| color::display_width(&c[i]))
+            .chain([color::display_width(HEADERS[i])])
+            .max()
+            .unwrap_or(0);
+    }
+    let print_line = |columns: [&str; 3], last: &str| {
+        let padded: Vec<String> =
+            columns.iter().zip(widths).map(|(c, w)| color::pad(c, w)).collect();
+        println!("{}  {}", padded.join("  "), last);
+    };
+
+    print_line([HEADERS[0], HEADERS[1], HEADERS[2]], HEADERS[3]);
+    let dashes = HEADERS.map(|h| "-".repeat(color::display_width(h)));
+    print_line([&dashes[0], &dashes[1], &dashes[2]], &dashes[3]);
+    for (row, cells) in rows.iter().zip(&cells) {
+        print_line([&cells[0], &cells[1], &cells[2]], &update_status(row));
+    }
+    println!();
+}
+
+/// 有更新为黄色、最新为绿色、检查失败为红色；非终端时不带颜色
+fn update_status(info: &UpdateInfo) -> String {
+    if info.latest_version == "未知" {
+        color::red("未知")
+    } else if info.has_update {
+        color::yellow("有更新")
+    } else {
+        color::green("最新")
+    }
+}
 
 #[cfg(target_os = "windows")]
-fn self_update(tarball_url: &str, exe_dir: &Path) -> Result<(), String> {
+fn self_update(
+    tarball_url: &str,
+    checksum: Option<&Checksum>,
+    exe_dir: &Path,
+) -> Result<(), String> {
     use std::env;
     
     let current_exe = env::current_exe()
@@ -520,7 +1734,7 @@ fn self_update(tarball_url: &str, exe_dir: &Path) -> Result<(), String> {
     fs::create_dir_all(&temp_dir)
         .map_err(|e| format!("创建临时目录失败: {}", e))?;
     
-    download_and_extract(tarball_url, &temp_dir)?;
+    download_and_extract(tarball_url, &temp_dir, checksum, None, &|| Ok(()))?;
     
     let new_exe = find_exe_in_dir(&temp_dir)
         .ok_or("下载的更新包中未找到可执行文件")?;
@@ -581,7 +1795,11 @@ exit
 }
 
 #[cfg(not(target_os = "windows"))]
-fn self_update(tarball_url: &str, exe_dir: &Path) -> Result<(), String> {
+fn self_update(
+    tarball_url: &str,
+    checksum: Option<&Checksum>,
+    exe_dir: &Path,
+) -> Result<(), String> {
     use std::env;
     use std::os::unix::fs::PermissionsExt;
     
@@ -595,7 +1813,7 @@ fn self_update(tarball_url: &str, exe_dir: &Path) -> Result<(), String> {
     fs::create_dir_all(&temp_dir)
         .map_err(|e| format!("创建临时目录失败: {}", e))?;
     
-    download_and_extract(tarball_url, &temp_dir)?;
+    download_and_extract(tarball_url, &temp_dir, checksum, None, &|| Ok(()))?;
     
     let new_exe = find_exe_in_dir(&temp_dir)
         .ok_or("下载的更新包中未找到可执行文件")?;
@@ -623,6 +1841,119 @@ fn self_update(tarball_url: &str, exe_dir: &Path) -> Result<(), String> {
     Ok(())
 }
 
+/// 嵌入到可执行文件中的版本标记，`--rollback` 据此读出备份文件的版本
+static VERSION_MARKER: &str = concat!("LLBOT_CLI_VERSION=", env!("CARGO_PKG_VERSION"), "\0");
+
+pub fn cli_version() -> &'static str {
+    std::hint::black_box(VERSION_MARKER)
+        .trim_start_matches("LLBOT_CLI_VERSION=")
+        .trim_end_matches('\0')
+}
+
+/// 从可执行文件内容中查找版本标记，旧版本没有标记时返回 None
+fn read_embedded_version(exe: &Path) -> Option<String> {
+    let data = fs::read(exe).ok()?;
+    let marker = b"LLBOT_CLI_VERSION=";
+    let start = data.windows(marker.len()).position(|w| w == marker)? + marker.len();
+    let len = data[start..].iter().take(32).position(|&b| b == 0)?;
+    String::from_utf8(data[start..start + len].to_vec()).ok()
+}
+
+/// 用 self_update 留下的 `<exe>.bak` 恢复上一个版本，当前版本会成为新的备份
+pub fn rollback(exe_dir: &Path) -> Result<(), String> {
+    let current_exe = std::env::current_exe()
+        .map_err(|e| format!("获取当前exe路径失败: {}", e))?;
+    let current_exe_name = current_exe.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(if cfg!(target_os = "windows") { "llbot.exe" } else { "llbot" });
+    let backup_exe = exe_dir.join(format!("{}.bak", current_exe_name));
+    
+    if !backup_exe.is_file() {
+        return Err(format!("未找到备份文件: {}", backup_exe.display()));
+    }
+    
+    let backup_version = read_embedded_version(&backup_exe).unwrap_or_else(|| "未知".to_string());
+    println!("当前版本: {}", cli_version());
+    println!("备份版本: {}", backup_version);
+    
+    if !prompt_yes_no("是否回滚到备份版本?") {
+        println!("回滚已取消");
+        return Ok(());
+    }
+    
+    swap_with_backup(&current_exe, &backup_exe)
+}
+
+#[cfg(target_os = "windows")]
+fn swap_with_backup(current_exe: &Path, backup_exe: &Path) -> Result<(), String> {
+    let swap_exe = current_exe.with_extension("exe.rollback");
+    let batch_script = std::env::temp_dir().join("_llbot_rollback.bat");
+    
+    // 批处理：等待当前进程退出 -> 当前版本改名 -> 恢复备份 -> 当前版本成为新备份
+    let script = format!(
+r#"@echo off
+chcp 65001 >nul
+echo 正在回滚 LLBot CLI，请稍候...
+
+:wait
+timeout /t 1 /nobreak >nul
+tasklist /FI "PID eq {pid}" 2>NUL | find /I "{pid}" >NUL
+if not errorlevel 1 goto wait
+
+move /y "{current}" "{swap}"
+move /y "{backup}" "{current}"
+
+if errorlevel 1 (
+    echo 回滚失败，正在恢复...
+    move /y "{swap}" "{current}"
+    pause
+    exit /b 1
+)
+
+move /y "{swap}" "{backup}"
+echo 回滚完成！
+timeout /t 2 /nobreak >nul
+(goto) 2>nul & del "%~f0"
+"#,
+        pid = std::process::id(),
+        current = current_exe.display(),
+        backup = backup_exe.display(),
+        swap = swap_exe.display(),
+    );
+    
+    fs::write(&batch_script, &script)
+        .map_err(|e| format!("创建回滚脚本失败: {}", e))?;
+    
+    println!("启动回滚脚本，程序即将退出...");
+    
+    Command::new("cmd")
+        .args(["/C", "start", "", "/MIN", batch_script.to_str().unwrap()])
+        .spawn()
+        .map_err(|e| format!("启动回滚脚本失败: {}", e))?;
+    
+    std::process::exit(0);
+}
+
+#[cfg(not(target_os = "windows"))]
+fn swap_with_backup(current_exe: &Path, backup_exe: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    
+    let swap_exe = current_exe.with_extension("rollback");
+    fs::rename(current_exe, &swap_exe)
+        .map_err(|e| format!("移动当前版本失败: {}", e))?;
+    if let Err(e) = fs::rename(backup_exe, current_exe) {
+        fs::rename(&swap_exe, current_exe).ok();
+        return Err(format!("恢复备份失败: {}", e));
+    }
+    fs::rename(&swap_exe, backup_exe)
+        .map_err(|e| format!("保存当前版本为备份失败: {}", e))?;
+    fs::set_permissions(current_exe, fs::Permissions::from_mode(0o755))
+        .map_err(|e| format!("设置权限失败: {}", e))?;
+    
+    println!("回滚完成！请重新启动程序。");
+    Ok(())
+}
+
 fn find_exe_in_dir(dir: &Path) -> Option<std::path::PathBuf> {
     let exe_name = if cfg!(target_os = "windows") { "llbot.exe" } else { "llbot" };
     